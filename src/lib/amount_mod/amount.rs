@@ -0,0 +1,253 @@
+use std::{error::Error, fmt, iter::Sum, ops::Neg, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of decimal places an amount is stored with
+pub const DECIMALS: u32 = 4;
+const SCALE: i64 = 10_i64.pow(DECIMALS);
+
+/// Exact fixed-point monetary amount with four decimal places.
+///
+/// Stored as a whole number of ten-thousandths so that repeated deposits and withdrawals never
+/// accumulate rounding error. Arithmetic is only offered checked, so that balances cannot
+/// silently wrap.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AmountError {
+    Invalid(String),
+    TooManyDecimals(String),
+    Overflow(String),
+    /// Transaction amount which is negative or zero
+    NotPositive(String),
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountError::Invalid(s) => write!(f, "invalid amount '{}'", s),
+            AmountError::TooManyDecimals(s) => {
+                write!(
+                    f,
+                    "amount '{}' has more than {} decimal places",
+                    s, DECIMALS
+                )
+            }
+            AmountError::Overflow(s) => write!(f, "amount '{}' is out of range", s),
+            AmountError::NotPositive(s) => write!(f, "amount '{}' is not positive", s),
+        }
+    }
+}
+
+impl Error for AmountError {}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    /// Parses the amount of a deposit or withdrawal. Unlike balances, these must be greater
+    /// than zero.
+    pub fn parse_positive(s: &str) -> Result<Amount, AmountError> {
+        let amount: Amount = s.parse()?;
+        if amount <= Amount::ZERO {
+            return Err(AmountError::NotPositive(s.to_string()));
+        }
+        Ok(amount)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AmountError::Invalid(s.to_string());
+
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+            return Err(invalid());
+        }
+        if fraction.len() > DECIMALS as usize {
+            return Err(AmountError::TooManyDecimals(s.to_string()));
+        }
+
+        // pad the fractional part out to a whole number of ten-thousandths
+        let fraction_units = format!("{:0<width$}", fraction, width = DECIMALS as usize)
+            .parse::<i64>()
+            .map_err(|_| invalid())?;
+        let whole_units = if whole.is_empty() {
+            0
+        } else {
+            whole
+                .parse::<i64>()
+                .map_err(|_| AmountError::Overflow(s.to_string()))?
+        };
+
+        let units = whole_units
+            .checked_mul(SCALE)
+            .and_then(|w| w.checked_add(fraction_units))
+            .ok_or_else(|| AmountError::Overflow(s.to_string()))?;
+
+        Ok(Amount(if negative { -units } else { units }))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            units / scale,
+            units % scale,
+            width = DECIMALS as usize
+        )
+    }
+}
+
+/// Saturates at the largest or smallest amount, as sums are only reported
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        Amount(iter.fold(0, |sum, amount| sum.saturating_add(amount.0)))
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(self.0.saturating_neg())
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl<'de> de::Visitor<'de> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "a decimal amount with at most {} decimal places",
+                    DECIMALS
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        d.deserialize_str(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Amount, AmountError};
+
+    fn amt(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_whole_and_fractional_amounts() {
+        assert_eq!(amt("1"), Amount(10_000));
+        assert_eq!(amt("1.5"), Amount(15_000));
+        assert_eq!(amt("0.0001"), Amount(1));
+        assert_eq!(amt(".25"), Amount(2_500));
+        assert_eq!(amt("-2.5"), Amount(-25_000));
+    }
+
+    #[test]
+    fn rejects_more_than_four_decimals() {
+        assert_eq!(
+            "1.00001".parse::<Amount>(),
+            Err(AmountError::TooManyDecimals("1.00001".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_amounts() {
+        assert!(matches!(
+            "922337203685478".parse::<Amount>(),
+            Err(AmountError::Overflow(_))
+        ));
+        assert!(matches!(
+            "99999999999999999999".parse::<Amount>(),
+            Err(AmountError::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        for s in ["", ".", "abc", "1.2.3", "1e5", "+1", "--1"] {
+            assert!(
+                matches!(s.parse::<Amount>(), Err(AmountError::Invalid(_))),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn transaction_amounts_must_be_positive() {
+        assert_eq!(Amount::parse_positive("1.5"), Ok(amt("1.5")));
+        for s in ["-1", "0", "0.0000"] {
+            assert_eq!(
+                Amount::parse_positive(s),
+                Err(AmountError::NotPositive(s.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn displays_four_decimal_places() {
+        assert_eq!(amt("1.5").to_string(), "1.5000");
+        assert_eq!(amt("-50").to_string(), "-50.0000");
+        assert_eq!(amt("0.0001").to_string(), "0.0001");
+    }
+
+    #[test]
+    fn repeated_arithmetic_is_exact() {
+        let mut total = Amount::ZERO;
+        (0..10_000).for_each(|_| total = total.checked_add(amt("0.1")).unwrap());
+        assert_eq!(total, amt("1000"));
+        (0..10_000).for_each(|_| total = total.checked_sub(amt("0.1")).unwrap());
+        assert_eq!(total, Amount::ZERO);
+    }
+
+    #[test]
+    fn checked_add_detects_overflow() {
+        let max = amt("922337203685477.5807");
+        assert_eq!(max.checked_add(amt("0.0001")), None);
+        assert_eq!(
+            max.checked_sub(amt("0.0001")),
+            Some(amt("922337203685477.5806"))
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use futures_util::{Stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::error_mod::error::EngineError;
+use crate::io_mod::csv_io::TxRow;
+use crate::snapshot_mod::snapshot::save_snapshot;
+use crate::{build_report, load_processor, Options, PaymentsReport};
+
+/// Rows which may be waiting for the processor before input streams are made to wait
+pub const STREAM_BUFFER: usize = 1024;
+
+/// Applies rows arriving concurrently from several streams, such as one per network feed.
+///
+/// Each stream is read by its own task, so rows from one stream are applied in the order it
+/// produced them, while rows from different streams interleave in arrival order. Readers wait
+/// once [`STREAM_BUFFER`] rows are queued, so a slow processor holds back its inputs rather
+/// than buffering without bound. Must be called from within a tokio runtime.
+///
+/// `options.state_in`, `state_out` and `dispute_policy` are honoured; rows are applied on the
+/// calling task, so `journal` and `threads` are not used.
+pub async fn process_streams<S>(
+    streams: Vec<S>,
+    options: Options,
+) -> Result<PaymentsReport, EngineError>
+where
+    S: Stream<Item = TxRow> + Send + Unpin + 'static,
+{
+    let mut row_processor = load_processor(&options)?;
+
+    let (sender, mut receiver) = mpsc::channel(STREAM_BUFFER);
+    for mut stream in streams {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            while let Some(row) = stream.next().await {
+                if sender.send(row).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    // the channel closes once every stream has finished
+    drop(sender);
+
+    while let Some(row) = receiver.recv().await {
+        let _ = row_processor.process_row(&row);
+    }
+
+    if let Some(path) = &options.state_out {
+        save_snapshot(path, &row_processor.snapshot())?;
+    }
+    Ok(build_report(&row_processor, Vec::new(), options.order))
+}
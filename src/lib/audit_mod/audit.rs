@@ -0,0 +1,333 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::amount_mod::amount::Amount;
+use crate::client_mod::client::{Account, Tx};
+use crate::client_mod::dispute_policy::{DisputePolicy, Movement};
+use crate::io_mod::csv_io::TxType;
+
+/// Where a row was read from. `input` counts the inputs of a run from 1, and is 0 for rows which
+/// did not come from an input, such as those replayed from a journal or applied directly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Source {
+    pub input: usize,
+    pub line: u64,
+}
+
+/// Balances of an account at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balances {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOp {
+    /// Account created by a deposit or withdrawal, with zero balances
+    Open,
+    /// Account carried over from a snapshot, with the balances it was saved with
+    Restore,
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl From<TxType> for AuditOp {
+    fn from(tx_type: TxType) -> Self {
+        match tx_type {
+            TxType::Deposit => AuditOp::Deposit,
+            TxType::Withdrawal => AuditOp::Withdrawal,
+            TxType::Dispute => AuditOp::Dispute,
+            TxType::Resolve => AuditOp::Resolve,
+            TxType::ChargeBack => AuditOp::Chargeback,
+        }
+    }
+}
+
+impl From<&Tx> for AuditOp {
+    fn from(tx: &Tx) -> Self {
+        match tx {
+            Tx::Deposit(_) => AuditOp::Deposit,
+            Tx::Withdraw(_) => AuditOp::Withdrawal,
+        }
+    }
+}
+
+/// A single change to an account, with its balances before and after. Every account starts
+/// with an `open` or `restore` event, and each later event starts from the balances the previous
+/// one left, so that the log alone is enough to rebuild every account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub op: AuditOp,
+    pub client: u16,
+    /// Absent for restored accounts
+    pub tx: Option<u32>,
+    /// Amount of the row, or for a dispute, resolve or chargeback that of the disputed
+    /// transaction
+    pub amount: Option<Amount>,
+    /// Whether a dispute, resolve or chargeback refers to a `deposit` or a `withdrawal`
+    #[serde(default)]
+    pub disputed: Option<AuditOp>,
+    /// Queued withdrawals paid out by a resolve, if any
+    #[serde(default)]
+    pub released: Option<Amount>,
+    pub before: Balances,
+    pub after: Balances,
+    pub input: usize,
+    pub line: u64,
+}
+
+#[derive(Debug)]
+pub enum AuditError {
+    Io(io::Error),
+    /// Event which could not be decoded, or does not follow on from the account's previous one
+    Invalid {
+        line: u64,
+        message: String,
+    },
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Io(e) => write!(f, "could not access audit log: {}", e),
+            AuditError::Invalid { line, message } => {
+                write!(f, "audit log line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl Error for AuditError {}
+
+impl From<io::Error> for AuditError {
+    fn from(e: io::Error) -> Self {
+        AuditError::Io(e)
+    }
+}
+
+/// JSON Lines file the audit events of a run are appended to, in the order they happened
+pub struct AuditLog {
+    wtr: BufWriter<File>,
+}
+
+impl AuditLog {
+    /// Creates the log at `path`, replacing any earlier one
+    pub fn create(path: &Path) -> Result<AuditLog, AuditError> {
+        Ok(AuditLog {
+            wtr: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn write(&mut self, events: &[AuditEvent]) -> Result<(), AuditError> {
+        for event in events {
+            serde_json::to_writer(&mut self.wtr, event).map_err(io::Error::from)?;
+            self.wtr.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), AuditError> {
+        self.wtr.flush()?;
+        self.wtr.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+/// Rebuilds every account from an audit log, ordered by client id. Fails if an event does not
+/// start from the balances its account was left with, or leaves balances other than those its
+/// op and amount lead to under `policy`, which must be the one the log was written with.
+pub fn replay_audit<R: Read>(
+    log: R,
+    policy: &dyn DisputePolicy,
+) -> Result<Vec<Account>, AuditError> {
+    let mut accounts: BTreeMap<u16, Balances> = BTreeMap::new();
+    let mut text = String::new();
+    let mut rdr = BufReader::new(log);
+    let mut line = 0;
+
+    loop {
+        text.clear();
+        if rdr.read_line(&mut text)? == 0 {
+            break;
+        }
+        line += 1;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let invalid = |message: String| AuditError::Invalid { line, message };
+
+        let event: AuditEvent =
+            serde_json::from_str(text.trim_end()).map_err(|e| invalid(e.to_string()))?;
+        let current = accounts.get(&event.client);
+        match (event.op, current) {
+            (AuditOp::Open | AuditOp::Restore, Some(_)) => {
+                return Err(invalid(format!("client {} opened twice", event.client)))
+            }
+            (AuditOp::Open, None) if event.after != Balances::default() => {
+                return Err(invalid(format!(
+                    "client {} opened with non-zero balances",
+                    event.client
+                )))
+            }
+            (AuditOp::Open | AuditOp::Restore, None) => {}
+            (_, None) => {
+                return Err(invalid(format!(
+                    "client {} changed before being opened",
+                    event.client
+                )))
+            }
+            (_, Some(current)) if *current != event.before => {
+                return Err(invalid(format!(
+                    "client {} balances do not follow on from its previous event",
+                    event.client
+                )))
+            }
+            (_, Some(_)) => {}
+        }
+        let follows = match event.op {
+            AuditOp::Open | AuditOp::Restore => true,
+            _ => expected_after(&event, policy) == Some(event.after),
+        };
+        if !follows {
+            return Err(invalid(format!(
+                "client {} balances after tx {} do not follow from its amount",
+                event.client,
+                event.tx.unwrap_or_default()
+            )));
+        }
+        let after = event.after;
+        if after.available.checked_add(after.held) != Some(after.total) {
+            return Err(invalid(format!(
+                "client {} total is not available plus held",
+                event.client
+            )));
+        }
+        accounts.insert(event.client, after);
+    }
+
+    Ok(accounts
+        .into_iter()
+        .map(|(client, balances)| Account {
+            client,
+            available: balances.available,
+            held: balances.held,
+            total: balances.total,
+            locked: balances.locked,
+            open_disputes: 0,
+            pending_withdrawals: Amount::ZERO,
+        })
+        .collect())
+}
+
+/// Balances a change to an account should leave it with, worked out from its op, amount and
+/// starting balances. `None` if the event does not describe a valid change.
+fn expected_after(event: &AuditEvent, policy: &dyn DisputePolicy) -> Option<Balances> {
+    let amount = event.amount?;
+    let disputed = || match event.disputed? {
+        AuditOp::Deposit => Some(Tx::Deposit(amount)),
+        AuditOp::Withdrawal => Some(Tx::Withdraw(amount)),
+        _ => None,
+    };
+    let (movement, released) = match event.op {
+        AuditOp::Deposit => (
+            Movement {
+                available: amount,
+                held: Amount::ZERO,
+            },
+            Amount::ZERO,
+        ),
+        AuditOp::Withdrawal => (
+            Movement {
+                available: -amount,
+                held: Amount::ZERO,
+            },
+            Amount::ZERO,
+        ),
+        AuditOp::Dispute => (policy.dispute(&disputed()?)?, Amount::ZERO),
+        AuditOp::Resolve => (
+            policy.resolve(&disputed()?),
+            event.released.unwrap_or(Amount::ZERO),
+        ),
+        AuditOp::Chargeback => (policy.chargeback(&disputed()?), Amount::ZERO),
+        AuditOp::Open | AuditOp::Restore => return None,
+    };
+
+    let before = event.before;
+    let moved = movement.available.checked_add(movement.held)?;
+    Some(Balances {
+        available: before
+            .available
+            .checked_add(movement.available)?
+            .checked_sub(released)?,
+        held: before.held.checked_add(movement.held)?,
+        total: before.total.checked_add(moved)?.checked_sub(released)?,
+        locked: before.locked || event.op == AuditOp::Chargeback,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replay_audit, AuditError};
+    use crate::client_mod::dispute_policy::DepositsOnly;
+
+    const OPEN: &str = "{\"op\":\"open\",\"client\":1,\"tx\":1,\"amount\":\"2\",\
+        \"before\":{\"available\":\"0\",\"held\":\"0\",\"total\":\"0\",\"locked\":false},\
+        \"after\":{\"available\":\"0\",\"held\":\"0\",\"total\":\"0\",\"locked\":false},\
+        \"input\":1,\"line\":2}";
+    const DEPOSIT: &str = "{\"op\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2\",\
+        \"before\":{\"available\":\"0\",\"held\":\"0\",\"total\":\"0\",\"locked\":false},\
+        \"after\":{\"available\":\"2\",\"held\":\"0\",\"total\":\"2\",\"locked\":false},\
+        \"input\":1,\"line\":2}";
+
+    #[test]
+    fn rebuilds_accounts_from_events() {
+        let log = format!("{}\n{}\n", OPEN, DEPOSIT);
+        let accounts = replay_audit(log.as_bytes(), &DepositsOnly).unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].total, "2".parse().unwrap());
+    }
+
+    #[test]
+    fn events_must_follow_on() {
+        let log = format!("{}\n{}\n{}\n", OPEN, DEPOSIT, DEPOSIT);
+        match replay_audit(log.as_bytes(), &DepositsOnly) {
+            Err(AuditError::Invalid { line, .. }) => assert_eq!(line, 3),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(replay_audit(DEPOSIT.as_bytes(), &DepositsOnly).is_err());
+    }
+
+    #[test]
+    fn balances_must_follow_from_the_amount() {
+        let tampered = DEPOSIT.replace(
+            "\"after\":{\"available\":\"2\",\"held\":\"0\",\"total\":\"2\"",
+            "\"after\":{\"available\":\"3\",\"held\":\"0\",\"total\":\"3\"",
+        );
+        assert_ne!(tampered, DEPOSIT);
+        let log = format!("{}\n{}\n", OPEN, tampered);
+        match replay_audit(log.as_bytes(), &DepositsOnly) {
+            Err(AuditError::Invalid { line, message }) => {
+                assert_eq!(line, 2);
+                assert_eq!(
+                    message,
+                    "client 1 balances after tx 1 do not follow from its amount"
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -1,32 +1,51 @@
 #![allow(unused)]
-use serde::{Serialize, Serializer};
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-#[derive(PartialEq, PartialOrd, Debug)]
-enum Tx {
-    Withdraw(f64),
-    Deposit(f64),
+use crate::amount_mod::amount::Amount;
+use crate::audit_mod::audit::Balances;
+
+use crate::io_mod::csv_io::TxType;
+
+use super::dispute_policy::{DisputePolicy, Movement};
+use super::history::HistoryEntry;
+use super::outcome::{Applied, TxError};
+
+/// Deposit or withdrawal accepted by an account
+#[derive(PartialEq, PartialOrd, Debug, Clone, Serialize, Deserialize)]
+pub enum Tx {
+    Withdraw(Amount),
+    Deposit(Amount),
+}
+
+impl Tx {
+    pub fn amount(&self) -> Amount {
+        match self {
+            Tx::Withdraw(amount) | Tx::Deposit(amount) => *amount,
+        }
+    }
+}
+
+/// Deposit or withdrawal kept by an account, which can be disputed while it is held
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientTx {
+    pub tx: u32,
+    pub kind: Tx,
+    pub disputed: bool,
 }
 
 #[derive(Debug)]
 struct RejectedTx {
-    amount: f64,
+    amount: Amount,
     after_disputes: HashSet<u32>,
 }
 
-fn fixed_width<S: Serializer>(x: &f64, s: S) -> Result<S::Ok, S::Error> {
-    s.serialize_str(&format!("{:.4}", x))
-}
-
 #[derive(Debug, Serialize)]
 pub struct Client {
     client: u16,
-    #[serde(serialize_with = "fixed_width")]
-    available: f64,
-    #[serde(serialize_with = "fixed_width")]
-    held: f64,
-    #[serde(serialize_with = "fixed_width")]
-    total: f64,
+    available: Amount,
+    held: Amount,
+    total: Amount,
     locked: bool,
     #[serde(skip)]
     txs: HashMap<u32, Tx>,
@@ -34,24 +53,126 @@ pub struct Client {
     disputed_txs: HashSet<u32>,
     #[serde(skip)]
     rejected_txs: Vec<RejectedTx>,
+    #[serde(skip)]
+    history: Vec<HistoryEntry>,
+}
+
+/// Balances of a single account, as listed in the accounts report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub client: u16,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    /// Transactions currently under dispute
+    #[serde(default)]
+    pub open_disputes: usize,
+    /// Sum of withdrawals queued until a dispute is resolved
+    #[serde(default)]
+    pub pending_withdrawals: Amount,
+}
+
+impl From<&Client> for Account {
+    fn from(c: &Client) -> Self {
+        Self {
+            client: c.client,
+            available: c.available,
+            held: c.held,
+            total: c.total,
+            locked: c.locked,
+            open_disputes: c.disputed_txs.len(),
+            pending_withdrawals: c.pending_withdrawals(),
+        }
+    }
+}
+
+/// Complete state of a client, including the transactions kept for future disputes. Ordered
+/// collections keep the serialized form stable between runs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientState {
+    pub client: u16,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    pub txs: BTreeMap<u32, Tx>,
+    pub disputed_txs: BTreeSet<u32>,
+    pub rejected_txs: Vec<RejectedTxState>,
+    /// Absent from version 1 snapshots
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejectedTxState {
+    pub amount: Amount,
+    pub after_disputes: BTreeSet<u32>,
+}
+
+impl From<&Client> for ClientState {
+    fn from(c: &Client) -> Self {
+        Self {
+            client: c.client,
+            available: c.available,
+            held: c.held,
+            total: c.total,
+            locked: c.locked,
+            txs: c.txs.iter().map(|(id, tx)| (*id, tx.clone())).collect(),
+            disputed_txs: c.disputed_txs.iter().copied().collect(),
+            rejected_txs: c
+                .rejected_txs
+                .iter()
+                .map(|r| RejectedTxState {
+                    amount: r.amount,
+                    after_disputes: r.after_disputes.iter().copied().collect(),
+                })
+                .collect(),
+            history: c.history.clone(),
+        }
+    }
+}
+
+impl From<ClientState> for Client {
+    fn from(state: ClientState) -> Self {
+        Self {
+            client: state.client,
+            available: state.available,
+            held: state.held,
+            total: state.total,
+            locked: state.locked,
+            txs: state.txs.into_iter().collect(),
+            disputed_txs: state.disputed_txs.into_iter().collect(),
+            rejected_txs: state
+                .rejected_txs
+                .into_iter()
+                .map(|r| RejectedTx {
+                    amount: r.amount,
+                    after_disputes: r.after_disputes.into_iter().collect(),
+                })
+                .collect(),
+            history: state.history,
+        }
+    }
 }
 
 impl Client {
     pub fn new(id: u16) -> Self {
         Self {
             client: id,
-            total: 0.0,
-            available: 0.0,
-            held: 0.0,
+            total: Amount::ZERO,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
             locked: false,
             txs: HashMap::new(),
             disputed_txs: HashSet::new(),
             rejected_txs: Vec::new(),
+            history: Vec::new(),
         }
     }
 
     #[allow(unused)]
-    fn with_state(id: u16, total: f64, available: f64, held: f64, locked: bool) -> Self {
+    fn with_state(id: u16, total: Amount, available: Amount, held: Amount, locked: bool) -> Self {
         Self {
             client: id,
             total,
@@ -61,17 +182,94 @@ impl Client {
             txs: HashMap::new(),
             disputed_txs: HashSet::new(),
             rejected_txs: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> u16 {
+        self.client
+    }
+
+    pub fn total(&self) -> Amount {
+        self.total
+    }
+
+    pub fn balances(&self) -> Balances {
+        Balances {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
         }
     }
 
-    pub fn deposit(&mut self, tx: u32, amount: f64) {
+    /// Deposit or withdrawal `tx`, if the account accepted it
+    pub fn tx(&self, tx: u32) -> Option<&Tx> {
+        self.txs.get(&tx)
+    }
+
+    /// Sum of withdrawals queued until a dispute is resolved
+    pub fn pending_withdrawals(&self) -> Amount {
+        self.rejected_txs.iter().map(|r| r.amount).sum()
+    }
+
+    /// Every transaction recorded with [`Client::record`], oldest first
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// Adds a transaction to the history, along with the balances it resulted in
+    pub fn record(
+        &mut self,
+        tx_type: TxType,
+        tx: u32,
+        amount: Option<Amount>,
+        result: Result<Applied, TxError>,
+    ) {
+        self.history.push(HistoryEntry {
+            tx_type,
+            tx,
+            amount,
+            result,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        });
+    }
+
+    /// Transactions kept for future disputes, ordered by transaction id
+    pub fn transactions(&self) -> Vec<ClientTx> {
+        let mut txs: Vec<ClientTx> = self
+            .txs
+            .iter()
+            .map(|(id, kind)| ClientTx {
+                tx: *id,
+                kind: kind.clone(),
+                disputed: self.disputed_txs.contains(id),
+            })
+            .collect();
+        txs.sort_by_key(|t| t.tx);
+        txs
+    }
+
+    pub fn deposit(&mut self, tx: u32, amount: Amount) -> Result<Applied, TxError> {
         if self.locked {
-            return;
+            return Err(TxError::AccountLocked);
         }
 
-        self.total += amount;
-        self.available += amount;
+        let (total, available) = match (
+            self.total.checked_add(amount),
+            self.available.checked_add(amount),
+        ) {
+            (Some(total), Some(available)) => (total, available),
+            _ => return Err(TxError::Overflow),
+        };
+
+        self.total = total;
+        self.available = available;
         self.txs.insert(tx, Tx::Deposit(amount));
+        Ok(Applied::Completed)
     }
 
     // Withdrawals can only be processed if they are not locked, under the total amount
@@ -79,12 +277,12 @@ impl Client {
     //
     // Rejected withdrawals are added to the rejected list, which are processed whenever a dispute
     // is resolved
-    pub fn withdraw(&mut self, tx: u32, amount: f64) {
-        if self.locked
-            || self.total < amount
-            || (self.available < amount && self.disputed_txs.is_empty())
-        {
-            return;
+    pub fn withdraw(&mut self, tx: u32, amount: Amount) -> Result<Applied, TxError> {
+        if self.locked {
+            return Err(TxError::AccountLocked);
+        }
+        if self.total < amount || (self.available < amount && self.disputed_txs.is_empty()) {
+            return Err(TxError::InsufficientFunds);
         }
 
         if self.available < amount && !self.disputed_txs.is_empty() {
@@ -95,247 +293,384 @@ impl Client {
                 after_disputes: curr_open_disputes,
             };
             self.rejected_txs.push(rejected_tx);
-            return;
+            return Ok(Applied::Queued);
         }
 
-        self.total -= amount;
-        self.available -= amount;
+        let (total, available) = match (
+            self.total.checked_sub(amount),
+            self.available.checked_sub(amount),
+        ) {
+            (Some(total), Some(available)) => (total, available),
+            _ => return Err(TxError::Overflow),
+        };
+
+        self.total = total;
+        self.available = available;
         self.txs.insert(tx, Tx::Withdraw(amount));
+        Ok(Applied::Completed)
     }
 
-    pub fn dispute(&mut self, tx: u32) {
+    pub fn dispute(&mut self, tx: u32, policy: &dyn DisputePolicy) -> Result<Applied, TxError> {
+        if self.locked {
+            return Err(TxError::AccountLocked);
+        }
         // transactions cannot be disputed more than once
-        let is_disputed = self.disputed_txs.contains(&tx);
-        if self.locked || is_disputed {
-            return;
+        if self.disputed_txs.contains(&tx) {
+            return Err(TxError::AlreadyDisputed);
         }
 
-        let maybe_tx_amount = self.txs.get(&tx);
-        // only deposits can be disputed (see readme)
-        if let Some(Tx::Deposit(tx_amount)) = maybe_tx_amount {
-            self.available -= tx_amount;
-            self.held += tx_amount;
-            self.disputed_txs.insert(tx);
-        }
+        let disputed = self.txs.get(&tx).ok_or(TxError::UnknownTx)?;
+        let movement = policy.dispute(disputed).ok_or(TxError::NotDisputable)?;
+        self.apply_movement(movement)?;
+        self.disputed_txs.insert(tx);
+        Ok(Applied::Completed)
     }
 
-    pub fn resolve(&mut self, tx: u32) {
-        if self.locked_or_not_disputed(tx) {
-            return;
-        }
-        let maybe_tx_amount = self.txs.get(&tx);
-        if let Some(Tx::Deposit(tx_amount)) = maybe_tx_amount {
-            self.available += tx_amount;
-            self.held -= tx_amount;
-            self.resolve_prev_rejected(tx);
-        }
-
-        // dispute is resolved
-        self.disputed_txs.remove(&tx);
-    }
+    pub fn resolve(&mut self, tx: u32, policy: &dyn DisputePolicy) -> Result<Applied, TxError> {
+        self.check_disputed(tx)?;
 
-    /// Attempts to resolve rejected tx (withdrawals), that occurred after a dispute.
-    fn resolve_prev_rejected(&mut self, resolved_tx: u32) {
-        let i_resolved_rej: Vec<usize> = self
-            .rejected_txs
-            .iter()
-            .enumerate()
-            .filter_map(|(i, r_tx)| {
-                let withdraw_occurred_before_resolved_tx =
-                    r_tx.after_disputes.contains(&resolved_tx);
-                let withdraw_within_avail = r_tx.amount <= self.available;
-
-                if withdraw_occurred_before_resolved_tx && withdraw_within_avail {
-                    self.available -= r_tx.amount;
-                    self.total -= r_tx.amount;
-                    return Some(i);
-                };
-                None
-            })
-            .collect();
+        let movement = policy.resolve(&self.txs[&tx]);
+        let (available, held, total) = self.moved(movement).ok_or(TxError::Overflow)?;
+        let (available, total, i_resolved_rej) =
+            self.resolve_prev_rejected(tx, available, total)?;
 
+        self.available = available;
+        self.held = held;
+        self.total = total;
         // remove txs which have now been accepted
         // order is reversed to preserve index of subsequent entries
         i_resolved_rej.iter().rev().for_each(|i| {
             self.rejected_txs.remove(*i);
         });
+
+        // dispute is resolved
+        self.disputed_txs.remove(&tx);
+        Ok(Applied::Completed)
     }
 
-    pub fn chargeback(&mut self, tx: u32) {
-        if self.locked_or_not_disputed(tx) {
-            return;
+    /// Attempts to resolve rejected tx (withdrawals), that occurred after a dispute, against the
+    /// balances left by the resolve. Returns the balances after those withdrawals, and which
+    /// of them were accepted.
+    fn resolve_prev_rejected(
+        &self,
+        resolved_tx: u32,
+        mut available: Amount,
+        mut total: Amount,
+    ) -> Result<(Amount, Amount, Vec<usize>), TxError> {
+        let mut i_resolved_rej = Vec::new();
+        for (i, r_tx) in self.rejected_txs.iter().enumerate() {
+            let withdraw_occurred_before_resolved_tx = r_tx.after_disputes.contains(&resolved_tx);
+            let withdraw_within_avail = r_tx.amount <= available;
+
+            if withdraw_occurred_before_resolved_tx && withdraw_within_avail {
+                available = available
+                    .checked_sub(r_tx.amount)
+                    .ok_or(TxError::Overflow)?;
+                total = total.checked_sub(r_tx.amount).ok_or(TxError::Overflow)?;
+                i_resolved_rej.push(i);
+            }
         }
+        Ok((available, total, i_resolved_rej))
+    }
 
-        let maybe_tx_amount = self.txs.get(&tx);
-        if let Some(Tx::Deposit(tx_amount)) = maybe_tx_amount {
-            self.held -= tx_amount;
-            self.total -= tx_amount;
-            self.locked = true;
-        }
+    pub fn chargeback(&mut self, tx: u32, policy: &dyn DisputePolicy) -> Result<Applied, TxError> {
+        self.check_disputed(tx)?;
+
+        let movement = policy.chargeback(&self.txs[&tx]);
+        self.apply_movement(movement)?;
+        self.locked = true;
+        Ok(Applied::Completed)
     }
 
-    fn locked_or_not_disputed(&self, tx: u32) -> bool {
-        let disputed = self.disputed_txs.contains(&tx);
-        self.locked || !disputed
+    /// Moves funds, leaving the account untouched if any balance would overflow
+    fn apply_movement(&mut self, movement: Movement) -> Result<(), TxError> {
+        let (available, held, total) = self.moved(movement).ok_or(TxError::Overflow)?;
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        Ok(())
+    }
+
+    /// Available, held and total balances after `movement`, or `None` if one would overflow
+    fn moved(&self, movement: Movement) -> Option<(Amount, Amount, Amount)> {
+        let available = self.available.checked_add(movement.available)?;
+        let held = self.held.checked_add(movement.held)?;
+        let total = self
+            .total
+            .checked_add(movement.available.checked_add(movement.held)?)?;
+        Some((available, held, total))
+    }
+
+    /// Resolves and chargebacks only apply to open disputes on unlocked accounts
+    fn check_disputed(&self, tx: u32) -> Result<(), TxError> {
+        if self.locked {
+            return Err(TxError::AccountLocked);
+        }
+        if !self.txs.contains_key(&tx) {
+            return Err(TxError::UnknownTx);
+        }
+        if !self.disputed_txs.contains(&tx) {
+            return Err(TxError::NotDisputed);
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Client;
+    use crate::amount_mod::amount::Amount;
+    use crate::client_mod::dispute_policy::{DepositsOnly, WithdrawalReversal};
+    use crate::client_mod::outcome::{Applied, TxError};
+
+    fn amt(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
 
     #[test]
     fn cannot_withdraw_under_avail() {
-        let mut client = Client::with_state(1, 10.0, 5.0, 5.0, false);
-        client.withdraw(1, 6.0);
-        assert_eq!(client.total, 10.0);
-        assert_eq!(client.available, 5.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("5.0"), amt("5.0"), false);
+        assert_eq!(
+            client.withdraw(1, amt("6.0")),
+            Err(TxError::InsufficientFunds)
+        );
+        assert_eq!(client.total, amt("10.0"));
+        assert_eq!(client.available, amt("5.0"));
     }
     #[test]
     fn can_withdraw_within_avail() {
-        let mut client = Client::with_state(1, 10.0, 5.0, 5.0, false);
-        client.withdraw(1, 5.0);
-        assert_eq!(client.total, 5.0);
-        assert_eq!(client.available, 0.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("5.0"), amt("5.0"), false);
+        client.withdraw(1, amt("5.0")).unwrap();
+        assert_eq!(client.total, amt("5.0"));
+        assert_eq!(client.available, amt("0.0"));
+    }
+
+    #[test]
+    fn overflowing_dispute_leaves_balances_untouched() {
+        let large = amt("900000000000000");
+        let mut client = Client::new(1);
+        client.deposit(1, large).unwrap();
+        client.withdraw(2, large).unwrap();
+        client.deposit(3, large).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+
+        // holding a second deposit would take held past the largest amount
+        assert_eq!(client.dispute(3, &DepositsOnly), Err(TxError::Overflow));
+        assert_eq!(client.held, large);
+        assert_eq!(client.available, Amount::ZERO);
+        assert_eq!(client.total, large);
+        assert!(!client.disputed_txs.contains(&3));
     }
 
     #[test]
     fn disputed_deposit_reduces_avail() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 5.0);
-        client.dispute(1);
-        assert_eq!(client.available, 10.0)
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        assert_eq!(client.available, amt("10.0"))
     }
 
     #[test]
     fn disputed_deposit_does_not_reduce_total() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 5.0);
-        client.dispute(1);
-        assert_eq!(client.total, 15.0)
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        assert_eq!(client.total, amt("15.0"))
     }
 
     #[test]
     fn dispute_will_increase_held_amount() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 5.0);
-        client.dispute(1);
-        assert_eq!(client.held, 5.0)
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        assert_eq!(client.held, amt("5.0"))
     }
 
     #[test]
     fn disputes_against_withdrawals_are_ignored() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.withdraw(1, 5.0);
-        client.dispute(1);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 5.0);
-        assert_eq!(client.available, 5.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.withdraw(1, amt("5.0")).unwrap();
+        assert_eq!(
+            client.dispute(1, &DepositsOnly),
+            Err(TxError::NotDisputable)
+        );
+        assert_eq!(client.held, amt("0.0"));
+        assert_eq!(client.total, amt("5.0"));
+        assert_eq!(client.available, amt("5.0"));
     }
 
     #[test]
     fn dispute_will_ignore_incorrect_tx() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 5.0);
-        client.dispute(2); // no transaction
-        assert_eq!(client.total, 15.0)
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        assert_eq!(client.dispute(2, &DepositsOnly), Err(TxError::UnknownTx)); // no transaction
+        assert_eq!(client.total, amt("15.0"))
     }
 
     #[test]
     fn dispute_is_one_per_tx() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 5.0);
-        client.dispute(1);
-        client.dispute(1);
-        assert_eq!(client.available, 10.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        assert_eq!(
+            client.dispute(1, &DepositsOnly),
+            Err(TxError::AlreadyDisputed)
+        );
+        assert_eq!(client.available, amt("10.0"));
     }
 
     #[test]
     fn resolve_will_release_held_funds() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 5.0);
-        client.dispute(1);
-        client.resolve(1);
-
-        assert_eq!(client.available, 15.0);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 15.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        client.resolve(1, &DepositsOnly).unwrap();
+
+        assert_eq!(client.available, amt("15.0"));
+        assert_eq!(client.held, amt("0.0"));
+        assert_eq!(client.total, amt("15.0"));
     }
 
     #[test]
     fn resolve_against_undisputed_tx_is_ignored() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 5.0);
-        client.deposit(2, 5.0);
-        client.dispute(1);
-        client.resolve(2);
-
-        assert_eq!(client.available, 15.0); // reduced by valid dispute
-        assert_eq!(client.held, 5.0); // held by valid dispute
-        assert_eq!(client.total, 20.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        client.deposit(2, amt("5.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        assert_eq!(client.resolve(2, &DepositsOnly), Err(TxError::NotDisputed));
+
+        assert_eq!(client.available, amt("15.0")); // reduced by valid dispute
+        assert_eq!(client.held, amt("5.0")); // held by valid dispute
+        assert_eq!(client.total, amt("20.0"));
     }
 
     #[test]
     fn resolve_against_non_tx_is_ignored() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.dispute(1);
-        assert_eq!(client.available, 10.0);
-        assert_eq!(client.held, 0.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        assert_eq!(client.dispute(1, &DepositsOnly), Err(TxError::UnknownTx));
+        assert_eq!(client.available, amt("10.0"));
+        assert_eq!(client.held, amt("0.0"));
     }
 
     #[test]
     fn chargeback_locks_account() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 10.0);
-        client.dispute(1);
-        client.chargeback(1);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("10.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        client.chargeback(1, &DepositsOnly).unwrap();
 
         assert!(client.locked)
     }
 
     #[test]
     fn chargeback_reduces_total() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 10.0);
-        assert_eq!(client.total, 20.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("10.0")).unwrap();
+        assert_eq!(client.total, amt("20.0"));
 
-        client.dispute(1);
-        client.chargeback(1);
-        assert_eq!(client.total, 10.0)
+        client.dispute(1, &DepositsOnly).unwrap();
+        client.chargeback(1, &DepositsOnly).unwrap();
+        assert_eq!(client.total, amt("10.0"))
     }
 
     #[test]
     fn chargeback_reduces_held() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 10.0);
-        client.dispute(1);
-        assert_eq!(client.held, 10.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("10.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        assert_eq!(client.held, amt("10.0"));
 
-        client.chargeback(1);
-        assert_eq!(client.held, 0.0)
+        client.chargeback(1, &DepositsOnly).unwrap();
+        assert_eq!(client.held, amt("0.0"))
     }
 
     #[test]
     fn chargeback_ignored_if_tx_does_not_exist() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 10.0);
-        client.dispute(1);
-
-        client.chargeback(2);
-        assert_eq!(client.held, 10.0);
-        assert_eq!(client.available, 10.0);
-        assert_eq!(client.total, 20.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("10.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+
+        assert_eq!(client.chargeback(2, &DepositsOnly), Err(TxError::UnknownTx));
+        assert_eq!(client.held, amt("10.0"));
+        assert_eq!(client.available, amt("10.0"));
+        assert_eq!(client.total, amt("20.0"));
         assert!(!client.locked);
     }
     #[test]
     fn chargeback_ignored_if_tx_undisputed() {
-        let mut client = Client::with_state(1, 10.0, 10.0, 0.0, false);
-        client.deposit(1, 10.0);
-
-        client.chargeback(1);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.available, 20.0);
-        assert_eq!(client.total, 20.0);
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("10.0")).unwrap();
+
+        assert_eq!(
+            client.chargeback(1, &DepositsOnly),
+            Err(TxError::NotDisputed)
+        );
+        assert_eq!(client.held, amt("0.0"));
+        assert_eq!(client.available, amt("20.0"));
+        assert_eq!(client.total, amt("20.0"));
         assert!(!client.locked);
     }
+
+    #[test]
+    fn deposit_ignored_if_balance_would_overflow() {
+        let max = amt("922337203685477.5807");
+        let mut client = Client::with_state(1, max, max, Amount::ZERO, false);
+        assert_eq!(client.deposit(1, amt("0.0001")), Err(TxError::Overflow));
+        assert_eq!(client.total, max);
+        assert_eq!(client.available, max);
+    }
+
+    #[test]
+    fn withdrawal_over_avail_is_queued_during_dispute() {
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.deposit(1, amt("5.0")).unwrap();
+        client.dispute(1, &DepositsOnly).unwrap();
+        assert_eq!(client.withdraw(2, amt("12.0")), Ok(Applied::Queued));
+        assert_eq!(client.available, amt("10.0"));
+    }
+
+    #[test]
+    fn locked_account_rejects_transactions() {
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), true);
+        assert_eq!(client.deposit(1, amt("5.0")), Err(TxError::AccountLocked));
+        assert_eq!(client.withdraw(2, amt("5.0")), Err(TxError::AccountLocked));
+        assert_eq!(
+            client.dispute(1, &DepositsOnly),
+            Err(TxError::AccountLocked)
+        );
+        assert_eq!(client.total, amt("10.0"));
+    }
+
+    #[test]
+    fn withdrawal_reversal_holds_disputed_withdrawal() {
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.withdraw(1, amt("4.0")).unwrap();
+        client.dispute(1, &WithdrawalReversal).unwrap();
+        assert_eq!(client.available, amt("6.0"));
+        assert_eq!(client.held, amt("4.0"));
+        assert_eq!(client.total, amt("10.0"));
+    }
+
+    #[test]
+    fn withdrawal_reversal_chargeback_credits_available() {
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.withdraw(1, amt("4.0")).unwrap();
+        client.dispute(1, &WithdrawalReversal).unwrap();
+        client.chargeback(1, &WithdrawalReversal).unwrap();
+        assert_eq!(client.available, amt("10.0"));
+        assert_eq!(client.held, amt("0.0"));
+        assert_eq!(client.total, amt("10.0"));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn withdrawal_reversal_resolve_keeps_withdrawal() {
+        let mut client = Client::with_state(1, amt("10.0"), amt("10.0"), amt("0.0"), false);
+        client.withdraw(1, amt("4.0")).unwrap();
+        client.dispute(1, &WithdrawalReversal).unwrap();
+        client.resolve(1, &WithdrawalReversal).unwrap();
+        assert_eq!(client.available, amt("6.0"));
+        assert_eq!(client.held, amt("0.0"));
+        assert_eq!(client.total, amt("6.0"));
+    }
 }
@@ -0,0 +1,95 @@
+use std::fmt::Debug;
+
+use crate::amount_mod::amount::Amount;
+
+use super::client::Tx;
+
+/// Change to an account's available and held funds. The total moves by their sum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Movement {
+    pub available: Amount,
+    pub held: Amount,
+}
+
+/// Decides which transactions can be disputed, and how funds move as a dispute is opened,
+/// resolved or charged back
+pub trait DisputePolicy: Debug + Send + Sync {
+    /// Movement when a dispute on `tx` is opened, or `None` if `tx` cannot be disputed
+    fn dispute(&self, tx: &Tx) -> Option<Movement>;
+
+    /// Movement when a dispute on `tx` is resolved and the transaction stands
+    fn resolve(&self, tx: &Tx) -> Movement;
+
+    /// Movement when a dispute on `tx` ends in a chargeback and the transaction is reversed
+    fn chargeback(&self, tx: &Tx) -> Movement;
+}
+
+/// Only deposits can be disputed (see readme). Disputed funds are moved from available to held
+/// until the dispute is resolved, or removed from the account on chargeback.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DepositsOnly;
+
+impl DisputePolicy for DepositsOnly {
+    fn dispute(&self, tx: &Tx) -> Option<Movement> {
+        match tx {
+            Tx::Deposit(amount) => Some(Movement {
+                available: -*amount,
+                held: *amount,
+            }),
+            Tx::Withdraw(_) => None,
+        }
+    }
+
+    fn resolve(&self, tx: &Tx) -> Movement {
+        let amount = tx.amount();
+        Movement {
+            available: amount,
+            held: -amount,
+        }
+    }
+
+    fn chargeback(&self, tx: &Tx) -> Movement {
+        Movement {
+            available: Amount::ZERO,
+            held: -tx.amount(),
+        }
+    }
+}
+
+/// Deposits are disputed as with [`DepositsOnly`]. Withdrawals can also be disputed: the
+/// withdrawn funds are provisionally credited back as held, then dropped again if the dispute
+/// is resolved, or released to available if the withdrawal is charged back.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WithdrawalReversal;
+
+impl DisputePolicy for WithdrawalReversal {
+    fn dispute(&self, tx: &Tx) -> Option<Movement> {
+        match tx {
+            Tx::Deposit(_) => DepositsOnly.dispute(tx),
+            Tx::Withdraw(amount) => Some(Movement {
+                available: Amount::ZERO,
+                held: *amount,
+            }),
+        }
+    }
+
+    fn resolve(&self, tx: &Tx) -> Movement {
+        match tx {
+            Tx::Deposit(_) => DepositsOnly.resolve(tx),
+            Tx::Withdraw(amount) => Movement {
+                available: Amount::ZERO,
+                held: -*amount,
+            },
+        }
+    }
+
+    fn chargeback(&self, tx: &Tx) -> Movement {
+        match tx {
+            Tx::Deposit(_) => DepositsOnly.chargeback(tx),
+            Tx::Withdraw(amount) => Movement {
+                available: *amount,
+                held: -*amount,
+            },
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+use crate::amount_mod::amount::Amount;
+use crate::io_mod::csv_io::TxType;
+
+use super::outcome::{Applied, TxError};
+
+/// Transaction a client sent, whether or not it was applied, with the balances it left the
+/// account with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub tx_type: TxType,
+    pub tx: u32,
+    pub amount: Option<Amount>,
+    pub result: Result<Applied, TxError>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl HistoryEntry {
+    /// `ok`, `queued` or `rejected`, as answered by the server
+    pub fn status(&self) -> &'static str {
+        match self.result {
+            Ok(Applied::Completed) => "ok",
+            Ok(Applied::Queued) => "queued",
+            Err(_) => "rejected",
+        }
+    }
+}
@@ -1 +1,4 @@
 pub mod client;
+pub mod dispute_policy;
+pub mod history;
+pub mod outcome;
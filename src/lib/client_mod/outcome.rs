@@ -0,0 +1,76 @@
+use std::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+/// Effect of a transaction that was accepted by an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Applied {
+    /// Balances were updated
+    Completed,
+    /// Withdrawal exceeds available funds while disputes are open; it is retried whenever one of
+    /// those disputes is resolved
+    Queued,
+}
+
+/// Reason a transaction had no effect. Serialized as its [`TxError::code`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxError {
+    AccountLocked,
+    InsufficientFunds,
+    UnknownClient,
+    UnknownTx,
+    DuplicateTx,
+    /// Transaction exists but belongs to another client, a possible fraud signal
+    CrossClientTx,
+    AlreadyDisputed,
+    NotDisputed,
+    NotDisputable,
+    Overflow,
+    MissingAmount,
+}
+
+impl TxError {
+    /// Rejections which should be brought to the attention of the risk team
+    pub fn is_flagged(&self) -> bool {
+        matches!(self, TxError::CrossClientTx)
+    }
+
+    /// Stable machine-readable reason code
+    pub fn code(&self) -> &'static str {
+        match self {
+            TxError::AccountLocked => "account_locked",
+            TxError::InsufficientFunds => "insufficient_funds",
+            TxError::UnknownClient => "unknown_client",
+            TxError::UnknownTx => "unknown_tx",
+            TxError::DuplicateTx => "duplicate_tx",
+            TxError::CrossClientTx => "cross_client_tx",
+            TxError::AlreadyDisputed => "already_disputed",
+            TxError::NotDisputed => "not_disputed",
+            TxError::NotDisputable => "not_disputable",
+            TxError::Overflow => "overflow",
+            TxError::MissingAmount => "missing_amount",
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            TxError::AccountLocked => "account is locked",
+            TxError::InsufficientFunds => "insufficient funds",
+            TxError::UnknownClient => "client does not exist",
+            TxError::UnknownTx => "transaction does not exist",
+            TxError::DuplicateTx => "transaction id has already been used",
+            TxError::CrossClientTx => "transaction belongs to another client",
+            TxError::AlreadyDisputed => "transaction is already disputed",
+            TxError::NotDisputed => "transaction is not disputed",
+            TxError::NotDisputable => "transaction cannot be disputed",
+            TxError::Overflow => "balance would overflow",
+            TxError::MissingAmount => "amount is missing",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for TxError {}
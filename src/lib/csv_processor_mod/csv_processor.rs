@@ -1,38 +1,335 @@
-use std::collections::HashMap;
+use std::{cmp::Reverse, collections::HashMap, mem, sync::Arc};
 
-use crate::client_mod::client::Client;
+use crate::amount_mod::amount::Amount;
+use crate::audit_mod::audit::{AuditEvent, AuditOp, Balances, Source};
+use crate::client_mod::client::{Client, ClientState};
+use crate::client_mod::dispute_policy::DisputePolicy;
+use crate::client_mod::outcome::{Applied, TxError};
 use crate::io_mod::csv_io::{TxRow, TxType};
+use crate::snapshot_mod::snapshot::{Snapshot, SNAPSHOT_VERSION};
+
+use super::tx_registry::TxRegistry;
+
+/// Input row which had no effect, along with the reason it was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub tx_type: TxType,
+    pub client: u16,
+    pub tx: u32,
+    pub error: TxError,
+}
+
+/// Order of accounts in reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountOrder {
+    /// Ascending client id
+    #[default]
+    ClientId,
+    /// Largest total first, ties broken by client id
+    Total,
+    /// Order in which accounts were opened, clients restored from a snapshot coming first
+    Insertion,
+}
 
 pub struct CsvProcessor {
     clients: HashMap<u16, Client>,
+    /// Position of each client in opening order: its index for clients restored from a
+    /// snapshot, and the number of restored clients plus the sequence number of the opening row
+    /// otherwise
+    opened: HashMap<u16, u64>,
+    restored: u64,
+    registry: TxRegistry,
+    /// Rejections tagged with the sequence number of their row, so that shards can be merged
+    /// back in input order. `None` unless they are kept, as they grow with the input.
+    rejections: Option<Vec<(u64, Rejection)>>,
+    /// Rejections flagged as possible fraud, counted whether or not rejections are kept
+    flagged: u64,
+    rows_processed: u64,
+    policy: Arc<dyn DisputePolicy>,
+    /// Whether each client's transactions are kept in its history
+    history: bool,
+    /// Audit events not yet taken, tagged with the sequence number of their row like rejections.
+    /// `None` unless auditing was enabled.
+    audit: Option<Vec<(u64, AuditEvent)>>,
 }
 
 impl CsvProcessor {
-    pub fn new() -> Self {
+    pub fn with_policy(policy: Arc<dyn DisputePolicy>) -> Self {
         Self {
             clients: HashMap::new(),
+            opened: HashMap::new(),
+            restored: 0,
+            registry: TxRegistry::new(),
+            rejections: None,
+            flagged: 0,
+            rows_processed: 0,
+            policy,
+            history: false,
+            audit: None,
         }
     }
 
-    pub fn process_rows(&mut self, rows: &[TxRow]) {
-        rows.iter().for_each(|row| self.process_row(row))
+    /// Keeps every transaction in its client's history from now on, see [`Client::history`]
+    pub fn record_history(&mut self, enabled: bool) {
+        self.history = enabled;
     }
 
-    fn process_row(&mut self, row: &TxRow) {
-        match row.tx_type {
-            // assuming deposit and withdrawal rows always contain an amount
-            TxType::Deposit => self.client_call(&|c| c.deposit(row.tx, row.amount.unwrap()), row),
-            TxType::Withdrawal => {
-                self.client_call(&|c| c.withdraw(row.tx, row.amount.unwrap()), row)
+    /// Keeps every rejected row from now on, see [`CsvProcessor::rejections`]
+    pub fn keep_rejections(&mut self, enabled: bool) {
+        self.rejections = enabled.then(|| self.rejections.take().unwrap_or_default());
+    }
+
+    /// Emits an audit event for every balance change from now on, see [`CsvProcessor::take_audit`].
+    /// Accounts which already exist are announced with a `restore` event.
+    pub fn record_audit(&mut self) {
+        let restored = self
+            .client_results(AccountOrder::Insertion)
+            .into_iter()
+            .map(|client| {
+                let event = AuditEvent {
+                    op: AuditOp::Restore,
+                    client: client.id(),
+                    tx: None,
+                    amount: None,
+                    disputed: None,
+                    released: None,
+                    before: Balances::default(),
+                    after: client.balances(),
+                    input: 0,
+                    line: 0,
+                };
+                (0, event)
+            })
+            .collect();
+        self.audit = Some(restored);
+    }
+
+    /// Audit events emitted since the last call, oldest first
+    pub fn take_audit(&mut self) -> Vec<AuditEvent> {
+        self.take_sequenced_audit().map_or_else(Vec::new, |events| {
+            events.into_iter().map(|(_, event)| event).collect()
+        })
+    }
+
+    /// Audit events emitted since the last call with the sequence number of their row, so that
+    /// [`ShardedProcessor`] workers can hand theirs over in batches. `None` unless auditing.
+    ///
+    /// [`ShardedProcessor`]: super::sharded_processor::ShardedProcessor
+    pub fn take_sequenced_audit(&mut self) -> Option<Vec<(u64, AuditEvent)>> {
+        self.audit.as_mut().map(mem::take)
+    }
+
+    /// Restores the clients and transaction registry saved by [`CsvProcessor::snapshot`]
+    pub fn from_snapshot(snapshot: Snapshot, policy: Arc<dyn DisputePolicy>) -> Self {
+        let mut processor = Self::with_policy(policy);
+        processor.registry = TxRegistry::from_owners(snapshot.tx_owners);
+        processor.restored = snapshot.clients.len() as u64;
+        for (i, state) in snapshot.clients.into_iter().enumerate() {
+            processor.opened.insert(state.client, i as u64);
+            processor.clients.insert(state.client, Client::from(state));
+        }
+        processor
+    }
+
+    /// Full account and transaction state, excluding this run's rejections. Clients are saved in
+    /// opening order, so that it carries over to the next run.
+    pub fn snapshot(&self) -> Snapshot {
+        let clients: Vec<ClientState> = self
+            .client_results(AccountOrder::Insertion)
+            .into_iter()
+            .map(ClientState::from)
+            .collect();
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            clients,
+            tx_owners: self.registry.owners().collect(),
+        }
+    }
+
+    /// Applies a single row, recording a rejection if it had no effect. Rejected rows are
+    /// otherwise only answered with their error, which callers applying whole inputs can
+    /// therefore ignore.
+    pub fn process_row(&mut self, row: &TxRow) -> Result<Applied, TxError> {
+        self.process_row_from(row, Source::default())
+    }
+
+    /// Applies a single row read from `source`, which is named by its audit events
+    pub fn process_row_from(&mut self, row: &TxRow, source: Source) -> Result<Applied, TxError> {
+        let checked = self.registry.check(row);
+        self.apply_checked(self.rows_processed, source, row, checked)
+    }
+
+    /// Applies a row which has already been checked against the transaction registry, `seq`
+    /// being its position in the input
+    pub fn apply_checked(
+        &mut self,
+        seq: u64,
+        source: Source,
+        row: &TxRow,
+        checked: Result<(), TxError>,
+    ) -> Result<Applied, TxError> {
+        let client = self.clients.get(&row.client);
+        let before = client.map(Client::balances);
+        // disputes, resolves and chargebacks are audited with the transaction they refer to, and
+        // the queued withdrawals they release
+        let referenced = client
+            .filter(|_| self.audit.is_some())
+            .and_then(|c| Some((c.tx(row.tx)?.clone(), c.pending_withdrawals())));
+        let outcome = checked.and_then(|_| self.apply(row));
+        self.rows_processed = seq + 1;
+        if let Some(client) = self.clients.get_mut(&row.client) {
+            self.opened.entry(row.client).or_insert(self.restored + seq);
+            if self.history {
+                client.record(row.tx_type, row.tx, row.amount, outcome);
+            }
+            if let Some(events) = &mut self.audit {
+                let event = |op, before, after| AuditEvent {
+                    op,
+                    client: row.client,
+                    tx: Some(row.tx),
+                    amount: row.amount,
+                    disputed: None,
+                    released: None,
+                    before,
+                    after,
+                    input: source.input,
+                    line: source.line,
+                };
+                // accounts are opened even by rows which are then rejected
+                if before.is_none() {
+                    let zero = Balances::default();
+                    events.push((seq, event(AuditOp::Open, zero, zero)));
+                }
+                // queued withdrawals leave balances untouched until released by a resolve
+                if outcome == Ok(Applied::Completed) {
+                    let before = before.unwrap_or_default();
+                    let mut applied = event(row.tx_type.into(), before, client.balances());
+                    if let (
+                        TxType::Dispute | TxType::Resolve | TxType::ChargeBack,
+                        Some((tx, pending)),
+                    ) = (row.tx_type, &referenced)
+                    {
+                        applied.amount = Some(tx.amount());
+                        applied.disputed = Some(tx.into());
+                        applied.released = pending
+                            .checked_sub(client.pending_withdrawals())
+                            .filter(|released| *released != Amount::ZERO);
+                    }
+                    events.push((seq, applied));
+                }
+            }
+        }
+
+        if let Err(error) = outcome {
+            if error.is_flagged() {
+                self.flagged += 1;
+            }
+            if let Some(rejections) = &mut self.rejections {
+                let rejection = Rejection {
+                    tx_type: row.tx_type,
+                    client: row.client,
+                    tx: row.tx,
+                    error,
+                };
+                rejections.push((seq, rejection));
             }
-            TxType::Dispute => self.client_call(&|c| c.dispute(row.tx), row),
-            TxType::Resolve => self.client_call(&|c| c.resolve(row.tx), row),
-            TxType::ChargeBack => self.client_call(&|c| c.chargeback(row.tx), row),
+        }
+        outcome
+    }
+
+    /// Number of rows processed so far, and so the sequence number of the next row
+    pub fn rows_processed(&self) -> u64 {
+        self.rows_processed
+    }
+
+    /// Splits clients into `shards` partitions by client id, for [`ShardedProcessor`] workers.
+    /// The registry stays with the caller, as transaction ids are checked across all clients.
+    ///
+    /// [`ShardedProcessor`]: super::sharded_processor::ShardedProcessor
+    pub fn into_shards(self, shards: usize) -> (TxRegistry, Vec<CsvProcessor>) {
+        let mut parts: Vec<CsvProcessor> = (0..shards)
+            .map(|_| CsvProcessor::with_policy(Arc::clone(&self.policy)))
+            .collect();
+        for (id, client) in self.clients {
+            let part = &mut parts[shard_of(id, shards)];
+            part.clients.insert(id, client);
+            part.opened.insert(id, self.opened[&id]);
+        }
+
+        parts.iter_mut().for_each(|p| {
+            p.rejections = self.rejections.as_ref().map(|_| Vec::new());
+            p.rows_processed = self.rows_processed;
+            p.restored = self.restored;
+            p.history = self.history;
+            p.audit = self.audit.as_ref().map(|_| Vec::new());
+        });
+        parts[0].rejections = self.rejections;
+        parts[0].flagged = self.flagged;
+        if let Some(events) = self.audit {
+            parts[0].audit = Some(events);
+        }
+        (self.registry, parts)
+    }
+
+    /// Reassembles a processor split by [`CsvProcessor::into_shards`], with rejections back in
+    /// input order. `audit` holds events already taken from the shards but not yet written,
+    /// which are merged with those the shards still hold.
+    pub fn from_shards(
+        registry: TxRegistry,
+        shards: Vec<CsvProcessor>,
+        audit: Vec<(u64, AuditEvent)>,
+    ) -> CsvProcessor {
+        let mut merged = CsvProcessor::with_policy(Arc::clone(&shards[0].policy));
+        merged.registry = registry;
+        merged.restored = shards[0].restored;
+        merged.history = shards[0].history;
+        merged.rejections = shards[0].rejections.as_ref().map(|_| Vec::new());
+        merged.audit = shards[0].audit.as_ref().map(|_| audit);
+        for shard in shards {
+            if let (Some(merged), Some(events)) = (&mut merged.audit, shard.audit) {
+                merged.extend(events);
+            }
+            merged.clients.extend(shard.clients);
+            merged.opened.extend(shard.opened);
+            if let (Some(merged), Some(rejections)) = (&mut merged.rejections, shard.rejections) {
+                merged.extend(rejections);
+            }
+            merged.flagged += shard.flagged;
+            merged.rows_processed = merged.rows_processed.max(shard.rows_processed);
+        }
+        if let Some(rejections) = &mut merged.rejections {
+            rejections.sort_by_key(|(seq, _)| *seq);
+        }
+        if let Some(events) = &mut merged.audit {
+            // stable, so that an account's open event stays ahead of the row which opened it
+            events.sort_by_key(|(seq, _)| *seq);
+        }
+        merged
+    }
+
+    fn apply(&mut self, row: &TxRow) -> Result<Applied, TxError> {
+        let policy = Arc::clone(&self.policy);
+        match row.tx_type {
+            TxType::Deposit => match row.amount {
+                Some(amount) => self.client_call(&|c| c.deposit(row.tx, amount), row),
+                None => Err(TxError::MissingAmount),
+            },
+            TxType::Withdrawal => match row.amount {
+                Some(amount) => self.client_call(&|c| c.withdraw(row.tx, amount), row),
+                None => Err(TxError::MissingAmount),
+            },
+            TxType::Dispute => self.client_call(&|c| c.dispute(row.tx, &*policy), row),
+            TxType::Resolve => self.client_call(&|c| c.resolve(row.tx, &*policy), row),
+            TxType::ChargeBack => self.client_call(&|c| c.chargeback(row.tx, &*policy), row),
         }
     }
 
     /// Handles the creation of new clients and delegates client method call to function pointer
-    fn client_call(&mut self, client_cmd: &dyn Fn(&mut Client), row: &TxRow) {
+    fn client_call(
+        &mut self,
+        client_cmd: &dyn Fn(&mut Client) -> Result<Applied, TxError>,
+        row: &TxRow,
+    ) -> Result<Applied, TxError> {
         let maybe_client = self.clients.get_mut(&row.client);
         match maybe_client {
             Some(client) => client_cmd(client),
@@ -40,14 +337,46 @@ impl CsvProcessor {
                 // new clients can only be created by withdraws and deposits
                 if let TxType::Deposit | TxType::Withdrawal = row.tx_type {
                     let mut c = Client::new(row.client);
-                    client_cmd(&mut c);
+                    let outcome = client_cmd(&mut c);
                     self.clients.insert(row.client, c);
+                    outcome
+                } else {
+                    Err(TxError::UnknownClient)
                 }
             }
         }
     }
 
-    pub fn client_results(&self) -> Vec<&Client> {
-        self.clients.iter().map(|(_, c)| c).collect()
+    pub fn client(&self, id: u16) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    pub fn client_results(&self, order: AccountOrder) -> Vec<&Client> {
+        let mut clients: Vec<&Client> = self.clients.values().collect();
+        match order {
+            AccountOrder::ClientId => clients.sort_by_key(|c| c.id()),
+            AccountOrder::Total => clients.sort_by_key(|c| (Reverse(c.total()), c.id())),
+            AccountOrder::Insertion => clients.sort_by_key(|c| self.opened.get(&c.id())),
+        }
+        clients
+    }
+
+    /// Rows which had no effect, in the order they were processed. Empty unless kept with
+    /// [`CsvProcessor::keep_rejections`].
+    pub fn rejections(&self) -> impl Iterator<Item = &Rejection> {
+        self.rejections
+            .iter()
+            .flatten()
+            .map(|(_, rejection)| rejection)
     }
+
+    /// Number of rejections flagged as possible fraud, see [`TxError::is_flagged`]
+    pub fn flagged_count(&self) -> u64 {
+        self.flagged
+    }
+}
+
+/// Partition owning `client` when accounts are split across `shards` workers
+pub fn shard_of(client: u16, shards: usize) -> usize {
+    client as usize % shards
 }
@@ -1 +1,3 @@
 pub mod csv_processor;
+pub mod sharded_processor;
+pub mod tx_registry;
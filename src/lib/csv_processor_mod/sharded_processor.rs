@@ -0,0 +1,224 @@
+use std::{
+    mem, panic,
+    sync::mpsc::{channel, sync_channel, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use crate::audit_mod::audit::{AuditEvent, Source};
+use crate::client_mod::outcome::TxError;
+use crate::io_mod::csv_io::TxRow;
+
+use super::csv_processor::{shard_of, CsvProcessor};
+use super::tx_registry::TxRegistry;
+
+/// Rows sent to a worker in one message, to keep channel overhead low
+const BATCH_SIZE: usize = 512;
+/// Batches a worker may have queued before the router blocks
+const QUEUED_BATCHES: usize = 16;
+
+/// Row routed to a worker, with its position in the input, where it was read from and the
+/// registry verdict
+type Routed = (u64, Source, TxRow, Result<(), TxError>);
+/// Batch of rows sent to a worker, with the sequence number of the next row routed to any
+/// worker. Every row before it which is bound for this worker is in the batch or an earlier one.
+type Batch = (u64, Vec<Routed>);
+/// Audit events of a batch handed back by a worker, with its index and the batch's mark
+type Progress = (usize, u64, Vec<(u64, AuditEvent)>);
+
+struct Worker {
+    sender: SyncSender<Batch>,
+    batch: Vec<Routed>,
+    handle: JoinHandle<CsvProcessor>,
+    /// Mark of the last batch sent to the worker
+    sent: u64,
+    /// Mark of the last batch whose audit events the worker has handed back
+    done: u64,
+}
+
+/// Audit events handed back by workers, waiting until every earlier row's events have arrived
+struct AuditMerge {
+    receiver: Receiver<Progress>,
+    events: Vec<(u64, AuditEvent)>,
+}
+
+/// Applies rows across worker threads, each owning the clients whose id maps to it.
+///
+/// Transaction ids are global, so the registry is checked on the calling thread before a row is
+/// routed. Every row for a client goes to the same worker in input order, so each account sees
+/// exactly the sequence of operations it would on a single thread.
+pub struct ShardedProcessor {
+    registry: TxRegistry,
+    workers: Vec<Worker>,
+    next_seq: u64,
+    /// `None` unless auditing
+    audit: Option<AuditMerge>,
+}
+
+impl ShardedProcessor {
+    /// Spreads the clients of `processor` across `shards` worker threads
+    pub fn new(mut processor: CsvProcessor, shards: usize) -> Self {
+        let next_seq = processor.rows_processed();
+        let (progress, audit) = match processor.take_sequenced_audit() {
+            Some(events) => {
+                let (progress, receiver) = channel();
+                (Some(progress), Some(AuditMerge { receiver, events }))
+            }
+            None => (None, None),
+        };
+        let (registry, parts) = processor.into_shards(shards.max(1));
+
+        let workers = parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut part)| {
+                let (sender, receiver) = sync_channel::<Batch>(QUEUED_BATCHES);
+                let progress = progress.clone();
+                let handle = thread::spawn(move || {
+                    for (mark, batch) in receiver {
+                        for (seq, source, row, checked) in batch {
+                            let _ = part.apply_checked(seq, source, &row, checked);
+                        }
+                        if let (Some(progress), Some(events)) =
+                            (&progress, part.take_sequenced_audit())
+                        {
+                            let _ = progress.send((i, mark, events));
+                        }
+                    }
+                    part
+                });
+                Worker {
+                    sender,
+                    batch: Vec::with_capacity(BATCH_SIZE),
+                    handle,
+                    sent: next_seq,
+                    done: next_seq,
+                }
+            })
+            .collect();
+
+        Self {
+            registry,
+            workers,
+            next_seq,
+            audit,
+        }
+    }
+
+    pub fn process_row(&mut self, row: TxRow, source: Source) {
+        let checked = self.registry.check(&row);
+        let shards = self.workers.len();
+        let worker = &mut self.workers[shard_of(row.client, shards)];
+
+        worker.batch.push((self.next_seq, source, row, checked));
+        self.next_seq += 1;
+        if worker.batch.len() == BATCH_SIZE {
+            let batch = mem::replace(&mut worker.batch, Vec::with_capacity(BATCH_SIZE));
+            worker.sent = self.next_seq;
+            // a closed channel means the worker panicked, which is surfaced by finish
+            let _ = worker.sender.send((self.next_seq, batch));
+        }
+    }
+
+    /// Audit events of every row before the oldest one a worker has yet to apply, in input
+    /// order. Later events are held back, so that the log matches a single-threaded run.
+    pub fn take_audit(&mut self) -> Vec<AuditEvent> {
+        let Some(merge) = &mut self.audit else {
+            return Vec::new();
+        };
+        let mut received = false;
+        for (i, mark, events) in merge.receiver.try_iter() {
+            self.workers[i].done = mark;
+            merge.events.extend(events);
+            received = true;
+        }
+        if received {
+            // stable, so that an account's open event stays ahead of the row which opened it
+            merge.events.sort_by_key(|(seq, _)| *seq);
+        }
+
+        let applied = self
+            .workers
+            .iter()
+            .map(|worker| {
+                if worker.done < worker.sent {
+                    worker.done
+                } else {
+                    worker.batch.first().map_or(self.next_seq, |(seq, ..)| *seq)
+                }
+            })
+            .min()
+            .unwrap_or(self.next_seq);
+        let ready = merge.events.partition_point(|(seq, _)| *seq < applied);
+        merge
+            .events
+            .drain(..ready)
+            .map(|(_, event)| event)
+            .collect()
+    }
+
+    /// Waits for every worker to drain its queue, then merges their clients back together
+    pub fn finish(self) -> CsvProcessor {
+        let next_seq = self.next_seq;
+        let parts = self
+            .workers
+            .into_iter()
+            .map(|worker| {
+                let _ = worker.sender.send((next_seq, worker.batch));
+                drop(worker.sender);
+                worker
+                    .handle
+                    .join()
+                    .unwrap_or_else(|e| panic::resume_unwind(e))
+            })
+            .collect();
+        // workers have finished, so every event they handed back has arrived
+        let audit = self.audit.map_or_else(Vec::new, |merge| {
+            let mut events = merge.events;
+            events.extend(merge.receiver.try_iter().flat_map(|(_, _, events)| events));
+            events
+        });
+        CsvProcessor::from_shards(self.registry, parts, audit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{ShardedProcessor, BATCH_SIZE};
+    use crate::audit_mod::audit::Source;
+    use crate::client_mod::dispute_policy::DepositsOnly;
+    use crate::csv_processor_mod::csv_processor::CsvProcessor;
+    use crate::io_mod::csv_io::{TxRow, TxType};
+
+    #[test]
+    fn audit_events_are_handed_over_before_finishing() {
+        let mut processor = CsvProcessor::with_policy(Arc::new(DepositsOnly));
+        processor.record_audit();
+        let mut sut = ShardedProcessor::new(processor, 2);
+        for tx in 1..=BATCH_SIZE as u32 {
+            let row = TxRow {
+                tx_type: TxType::Deposit,
+                client: 1,
+                tx,
+                amount: Some("1".parse().unwrap()),
+            };
+            sut.process_row(row, Source::default());
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = Vec::new();
+        while events.is_empty() {
+            assert!(Instant::now() < deadline, "no events before finishing");
+            thread::sleep(Duration::from_millis(1));
+            events = sut.take_audit();
+        }
+        // an open event, then every deposit
+        assert_eq!(events.len(), BATCH_SIZE + 1);
+        assert!(sut.finish().take_audit().is_empty());
+    }
+}
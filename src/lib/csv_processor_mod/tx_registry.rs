@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use crate::client_mod::outcome::TxError;
+use crate::io_mod::csv_io::{TxRow, TxType};
+
+/// Owner of every transaction id seen across all clients. Ids are globally unique, so a deposit
+/// or withdrawal reusing one is rejected, whichever client it names.
+#[derive(Debug, Default)]
+pub struct TxRegistry {
+    owners: HashMap<u32, u16>,
+}
+
+impl TxRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_owners(owners: impl IntoIterator<Item = (u32, u16)>) -> Self {
+        Self {
+            owners: owners.into_iter().collect(),
+        }
+    }
+
+    pub fn owners(&self) -> impl Iterator<Item = (u32, u16)> + '_ {
+        self.owners.iter().map(|(tx, client)| (*tx, *client))
+    }
+
+    /// Rejects rows whose transaction id conflicts with one seen before, or which refer to a
+    /// transaction that does not belong to the client named in the row
+    pub fn check(&mut self, row: &TxRow) -> Result<(), TxError> {
+        match row.tx_type {
+            TxType::Deposit | TxType::Withdrawal => self.register(row.tx, row.client),
+            TxType::Dispute | TxType::Resolve | TxType::ChargeBack => {
+                self.check_owner(row.tx, row.client)
+            }
+        }
+    }
+
+    /// Claims `tx` for a new deposit or withdrawal made by `client`
+    pub fn register(&mut self, tx: u32, client: u16) -> Result<(), TxError> {
+        if self.owners.contains_key(&tx) {
+            return Err(TxError::DuplicateTx);
+        }
+        self.owners.insert(tx, client);
+        Ok(())
+    }
+
+    /// Checks that `tx` exists and was made by `client`, before it is disputed, resolved or
+    /// charged back
+    pub fn check_owner(&self, tx: u32, client: u16) -> Result<(), TxError> {
+        match self.owners.get(&tx) {
+            None => Err(TxError::UnknownTx),
+            Some(owner) if *owner != client => Err(TxError::CrossClientTx),
+            Some(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use crate::client_mod::client::{Account, ClientTx};
+use crate::client_mod::dispute_policy::{DepositsOnly, DisputePolicy};
+use crate::client_mod::history::HistoryEntry;
+use crate::client_mod::outcome::{Applied, TxError};
+use crate::csv_processor_mod::csv_processor::{AccountOrder, CsvProcessor, Rejection};
+use crate::error_mod::error::EngineError;
+use crate::io_mod::csv_io::{ParseMode, RowError, TxRow};
+use crate::io_mod::input::{classify_row, read_rows, InputFormat, RowOutcome};
+use crate::io_mod::report::{write_statement, ReportFormat};
+use crate::{build_report, load_processor, Options, PaymentsReport};
+
+/// Payments engine for applying transactions directly, without going through csv.
+///
+/// ```
+/// use toy_payments_lib::{Applied, Engine, TxRow, TxType};
+///
+/// let mut engine = Engine::new();
+/// let deposit = TxRow {
+///     tx_type: TxType::Deposit,
+///     client: 1,
+///     tx: 1,
+///     amount: Some("2.5".parse().unwrap()),
+/// };
+/// assert_eq!(engine.apply(&deposit), Ok(Applied::Completed));
+/// assert_eq!(engine.account(1).unwrap().available.to_string(), "2.5000");
+/// ```
+pub struct Engine {
+    processor: CsvProcessor,
+    /// Inputs given to [`Engine::process_input`] so far
+    inputs: usize,
+}
+
+impl Engine {
+    /// Engine with no clients, where only deposits can be disputed
+    pub fn new() -> Self {
+        Self::with_policy(Arc::new(DepositsOnly))
+    }
+
+    /// Engine with no clients, using `policy` to decide which transactions can be disputed
+    pub fn with_policy(policy: Arc<dyn DisputePolicy>) -> Self {
+        Self {
+            processor: CsvProcessor::with_policy(policy),
+            inputs: 0,
+        }
+    }
+
+    /// Keeps every rejected transaction from now on, for [`Engine::rejections`] and the report.
+    /// Off unless asked for, as kept rejections grow with the input.
+    pub fn keep_rejections(&mut self, enabled: bool) {
+        self.processor.keep_rejections(enabled);
+    }
+
+    /// Engine using the dispute policy of `options`, resumed from `state_in` if given. Rejected
+    /// transactions are only kept with `keep_rejections`.
+    pub fn from_options(options: &Options) -> Result<Self, EngineError> {
+        Ok(Self {
+            processor: load_processor(options)?,
+            inputs: 0,
+        })
+    }
+
+    /// Applies a single transaction. Rejected transactions have no effect on balances, and are
+    /// kept for the report if [`Engine::keep_rejections`] asked for them.
+    pub fn apply(&mut self, tx: &TxRow) -> Result<Applied, TxError> {
+        self.processor.process_row(tx)
+    }
+
+    /// Applies every row of a csv input. In lenient mode malformed rows are skipped and
+    /// returned, in strict mode the first one is an error.
+    pub fn process_csv<R: Read>(
+        &mut self,
+        reader: R,
+        mode: ParseMode,
+    ) -> Result<Vec<RowError>, EngineError> {
+        self.process_input(reader, InputFormat::Csv, mode)
+    }
+
+    /// Applies every row of an input in the given format, handling malformed rows as
+    /// [`Engine::process_csv`] does. Their errors name the input by counting those this engine
+    /// has been given from 1.
+    pub fn process_input<R: Read>(
+        &mut self,
+        reader: R,
+        format: InputFormat,
+        mode: ParseMode,
+    ) -> Result<Vec<RowError>, EngineError> {
+        self.inputs += 1;
+        let mut malformed = Vec::new();
+        for row in read_rows(reader, format) {
+            match classify_row(row, self.inputs, mode)? {
+                RowOutcome::Valid(row) => {
+                    let _ = self.processor.process_row(&row);
+                }
+                RowOutcome::Malformed(error) => malformed.push(error),
+            }
+        }
+        Ok(malformed)
+    }
+
+    pub fn account(&self, client: u16) -> Option<Account> {
+        self.processor.client(client).map(Account::from)
+    }
+
+    /// Every account, ordered by client id
+    pub fn accounts(&self) -> Vec<Account> {
+        self.accounts_by(AccountOrder::ClientId)
+    }
+
+    pub fn accounts_by(&self, order: AccountOrder) -> Vec<Account> {
+        self.processor
+            .client_results(order)
+            .into_iter()
+            .map(Account::from)
+            .collect()
+    }
+
+    /// Deposits and withdrawals a client has made, with whether each is currently disputed
+    pub fn transactions(&self, client: u16) -> Vec<ClientTx> {
+        self.processor
+            .client(client)
+            .map_or_else(Vec::new, |c| c.transactions())
+    }
+
+    /// Transactions which had no effect, in the order they were applied, if they are kept
+    pub fn rejections(&self) -> impl Iterator<Item = &Rejection> {
+        self.processor.rejections()
+    }
+
+    /// Every transaction a client sent, oldest first, with the balances it resulted in. Only
+    /// recorded when the engine was created with [`Options::history`], and `None` if the client
+    /// has no account.
+    pub fn history(&self, client: u16) -> Option<&[HistoryEntry]> {
+        self.processor.client(client).map(|c| c.history())
+    }
+
+    /// Writes a client's history as a statement, which is empty if the client has no account
+    pub fn write_statement<W: Write>(
+        &self,
+        client: u16,
+        out: W,
+        format: ReportFormat,
+    ) -> Result<(), EngineError> {
+        write_statement(self.history(client).unwrap_or_default(), out, format)
+    }
+
+    /// Accounts csv and rejected transactions, as produced by a csv run
+    pub fn into_report(self) -> PaymentsReport {
+        build_report(&self.processor, Vec::new(), AccountOrder::ClientId)
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Engine;
+    use crate::client_mod::outcome::{Applied, TxError};
+    use crate::io_mod::csv_io::{TxRow, TxType};
+
+    fn row(tx_type: TxType, client: u16, tx: u32, amount: Option<&str>) -> TxRow {
+        TxRow {
+            tx_type,
+            client,
+            tx,
+            amount: amount.map(|a| a.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn applies_transactions_and_lists_accounts_by_client() {
+        let mut engine = Engine::new();
+        engine.keep_rejections(true);
+        assert_eq!(
+            engine.apply(&row(TxType::Deposit, 2, 1, Some("5"))),
+            Ok(Applied::Completed)
+        );
+        assert_eq!(
+            engine.apply(&row(TxType::Deposit, 1, 2, Some("1"))),
+            Ok(Applied::Completed)
+        );
+        assert_eq!(
+            engine.apply(&row(TxType::Withdrawal, 1, 3, Some("2"))),
+            Err(TxError::InsufficientFunds)
+        );
+
+        let clients: Vec<u16> = engine.accounts().iter().map(|a| a.client).collect();
+        assert_eq!(clients, vec![1, 2]);
+        assert_eq!(engine.account(2).unwrap().total, "5".parse().unwrap());
+        assert!(engine.account(3).is_none());
+
+        let report = engine.into_report();
+        assert_eq!(report.rejections.len(), 1);
+        assert_eq!(report.rejections[0].error, TxError::InsufficientFunds);
+    }
+
+    #[test]
+    fn rejections_are_only_kept_when_asked_for() {
+        let mut engine = Engine::new();
+        let withdrawal = row(TxType::Withdrawal, 1, 1, Some("2"));
+        assert_eq!(engine.apply(&withdrawal), Err(TxError::InsufficientFunds));
+        assert_eq!(engine.rejections().count(), 0);
+
+        engine.keep_rejections(true);
+        let withdrawal = row(TxType::Withdrawal, 1, 2, Some("2"));
+        assert_eq!(engine.apply(&withdrawal), Err(TxError::InsufficientFunds));
+        assert_eq!(engine.rejections().count(), 1);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{error::Error, fmt, io};
+
+use crate::audit_mod::audit::AuditError;
+use crate::io_mod::csv_io::{InputError, RowError};
+use crate::journal_mod::journal::JournalError;
+use crate::snapshot_mod::snapshot::SnapshotError;
+
+/// Reason a run could not complete
+#[derive(Debug)]
+pub enum EngineError {
+    /// Input or output could not be read or written
+    Io(io::Error),
+    /// Input is not well-formed csv or JSON, such as invalid utf-8 or a row with the wrong
+    /// number of fields, at `byte` offset into the input
+    Syntax {
+        error: RowError,
+        byte: u64,
+    },
+    /// Input rows or header do not describe transactions, such as an unknown type, a bad client
+    /// or transaction id, or a missing column
+    Schema(RowError),
+    /// A row's amount could not be read
+    Amount(RowError),
+    /// A report could not be serialized
+    Output(String),
+    /// Options which cannot be used together
+    Options(String),
+    Snapshot(SnapshotError),
+    Journal(JournalError),
+    Audit(AuditError),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Io(e) => write!(f, "i/o error: {}", e),
+            EngineError::Syntax { error, byte } => write!(f, "{} (byte {})", error, byte),
+            EngineError::Schema(e) | EngineError::Amount(e) => e.fmt(f),
+            EngineError::Output(msg) => write!(f, "could not write output: {}", msg),
+            EngineError::Options(msg) => write!(f, "invalid options: {}", msg),
+            EngineError::Snapshot(e) => e.fmt(f),
+            EngineError::Journal(e) => e.fmt(f),
+            EngineError::Audit(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::Io(e) => Some(e),
+            EngineError::Snapshot(e) => Some(e),
+            EngineError::Journal(e) => Some(e),
+            EngineError::Audit(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(e: io::Error) -> Self {
+        EngineError::Io(e)
+    }
+}
+
+impl From<SnapshotError> for EngineError {
+    fn from(e: SnapshotError) -> Self {
+        EngineError::Snapshot(e)
+    }
+}
+
+impl From<JournalError> for EngineError {
+    fn from(e: JournalError) -> Self {
+        EngineError::Journal(e)
+    }
+}
+
+impl From<AuditError> for EngineError {
+    fn from(e: AuditError) -> Self {
+        EngineError::Audit(e)
+    }
+}
+
+impl From<InputError> for EngineError {
+    fn from(e: InputError) -> Self {
+        match e {
+            InputError::Syntax { error, byte } => EngineError::Syntax { error, byte },
+            InputError::Schema(error) => EngineError::Schema(error),
+            InputError::Amount(error) => EngineError::Amount(error),
+            InputError::Header(message) => EngineError::Schema(RowError {
+                input: 0,
+                line: 1,
+                column: None,
+                message,
+            }),
+            InputError::Read(e) => EngineError::Io(e),
+            InputError::Io(e) => {
+                let message = e.to_string();
+                let position = e.position().cloned();
+                match e.into_kind() {
+                    csv::ErrorKind::Io(e) => EngineError::Io(e),
+                    _ => EngineError::Syntax {
+                        error: RowError {
+                            input: 0,
+                            line: position.as_ref().map_or(0, |p| p.line()),
+                            column: None,
+                            message,
+                        },
+                        byte: position.as_ref().map_or(0, |p| p.byte()),
+                    },
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression applied to an input or report. Gzip and zstd each need their cargo feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression suggested by a file name, such as `transactions.csv.gz`
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Fails if this build was made without the feature the compression needs, so that callers
+    /// can find out before doing any work whose result they could not write
+    pub fn ensure_supported(self) -> io::Result<()> {
+        match self {
+            Compression::Gzip if !cfg!(feature = "gzip") => Err(unsupported("gzip")),
+            Compression::Zstd if !cfg!(feature = "zstd") => Err(unsupported("zstd")),
+            _ => Ok(()),
+        }
+    }
+
+    fn from_magic(head: &[u8]) -> Option<Compression> {
+        if head.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Opens an input file, or stdin for `-`, decompressing it on the fly if needed
+pub fn open_input(path: &OsStr) -> io::Result<Box<dyn Read>> {
+    if path == "-" {
+        return decompress(io::stdin().lock(), Compression::None);
+    }
+    let path = Path::new(path);
+    decompress(File::open(path)?, Compression::from_path(path))
+}
+
+/// Wraps `reader` to decompress it on the fly. Leading magic bytes take precedence, and `hint`,
+/// usually taken from the file name, is used when they are not recognised.
+pub fn decompress<'a, R: Read + 'a>(
+    reader: R,
+    hint: Compression,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::from_magic(reader.fill_buf()?).unwrap_or(hint);
+    match compression {
+        Compression::None => Ok(Box::new(reader)),
+        Compression::Gzip => gzip_decoder(reader),
+        Compression::Zstd => zstd_decoder(reader),
+    }
+}
+
+/// Writes `data` to `out`, compressing it first if asked to
+pub fn write_compressed<W: Write>(out: W, data: &[u8], compression: Compression) -> io::Result<()> {
+    let mut writer = CompressedWriter::new(out, compression)?;
+    writer.write_all(data)?;
+    writer.finish().map(|_| ())
+}
+
+/// Compresses everything written to it on the fly. [`CompressedWriter::finish`] must be called
+/// once done, to write the end of the compressed stream.
+pub struct CompressedWriter<W: Write> {
+    encoder: Encoder<W>,
+}
+
+enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(out: W, compression: Compression) -> io::Result<Self> {
+        let encoder = match compression {
+            Compression::None => Encoder::Plain(out),
+            Compression::Gzip => gzip_encoder(out)?,
+            Compression::Zstd => zstd_encoder(out)?,
+        };
+        Ok(Self { encoder })
+    }
+
+    /// Ends the compressed stream and flushes it, returning the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        let finished: io::Result<W> = match self.encoder {
+            Encoder::Plain(out) => Ok(out),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        };
+        let mut out = finished?;
+        out.flush()?;
+        Ok(out)
+    }
+
+    fn inner(&mut self) -> &mut dyn Write {
+        match &mut self.encoder {
+            Encoder::Plain(out) => out,
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+fn unsupported(format: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{} support requires building with the {} feature",
+            format, format
+        ),
+    )
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_decoder<'a, R: BufRead + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    // concatenated members, as produced by appending to a .gz file, are read as one stream
+    Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_decoder<'a, R: BufRead + 'a>(_reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Err(unsupported("gzip"))
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_encoder<W: Write>(out: W) -> io::Result<Encoder<W>> {
+    let encoder = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+    Ok(Encoder::Gzip(encoder))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_encoder<W: Write>(_out: W) -> io::Result<Encoder<W>> {
+    Err(unsupported("gzip"))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decoder<'a, R: BufRead + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder<'a, R: BufRead + 'a>(_reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Err(unsupported("zstd"))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_encoder<W: Write>(out: W) -> io::Result<Encoder<W>> {
+    Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(out, 0)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_encoder<W: Write>(_out: W) -> io::Result<Encoder<W>> {
+    Err(unsupported("zstd"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, path::Path};
+
+    use super::{decompress, write_compressed, Compression};
+
+    #[test]
+    fn compression_is_taken_from_extension() {
+        let from = |p: &str| Compression::from_path(Path::new(p));
+        assert_eq!(from("day1.csv.gz"), Compression::Gzip);
+        assert_eq!(from("day1.csv.zst"), Compression::Zstd);
+        assert_eq!(from("day1.csv"), Compression::None);
+    }
+
+    #[test]
+    fn plain_input_passes_through() {
+        let mut out = String::new();
+        decompress("type,client".as_bytes(), Compression::None)
+            .unwrap()
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "type,client");
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let formats = [
+            (cfg!(feature = "gzip"), Compression::Gzip),
+            (cfg!(feature = "zstd"), Compression::Zstd),
+        ];
+        for (enabled, compression) in formats {
+            let mut compressed = Vec::new();
+            let written = write_compressed(&mut compressed, b"client\n1\n", compression);
+            assert_eq!(written.is_ok(), enabled, "{:?}", compression);
+            assert_eq!(compression.ensure_supported().is_ok(), enabled);
+            if !enabled {
+                continue;
+            }
+
+            // magic bytes are detected without a hint
+            let mut out = String::new();
+            decompress(compressed.as_slice(), Compression::None)
+                .unwrap()
+                .read_to_string(&mut out)
+                .unwrap();
+            assert_eq!(out, "client\n1\n");
+        }
+    }
+}
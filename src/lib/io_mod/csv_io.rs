@@ -1,54 +1,397 @@
-use std::{error::Error, ffi::OsString, fs::File};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+    str::FromStr,
+};
 
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer, Serialize};
 
-use crate::client_mod::client::Client;
+use crate::{
+    amount_mod::amount::{Amount, AmountError},
+    client_mod::client::{Account, Client},
+    csv_processor_mod::csv_processor::Rejection,
+    error_mod::error::EngineError,
+};
 
-#[derive(Debug, Deserialize)]
+use super::report::{write_accounts, ReportFormat};
+
+/// A single transaction, as read from one input row. Deposits and withdrawals carry an amount,
+/// disputes, resolves and chargebacks name the transaction `tx` they refer to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TxRow {
-    #[serde(alias = "type")]
+    #[serde(rename(serialize = "type"), alias = "type")]
     pub tx_type: TxType,
     pub client: u16,
     pub tx: u32,
-    pub amount: Option<f64>,
+    #[serde(default, deserialize_with = "positive_amount")]
+    pub amount: Option<Amount>,
+}
+
+/// Rejects negative and zero amounts in rows decoded with serde, such as over HTTP
+fn positive_amount<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Amount>, D::Error> {
+    match Option::<Amount>::deserialize(d)? {
+        Some(amount) if amount <= Amount::ZERO => Err(de::Error::custom(AmountError::NotPositive(
+            amount.to_string(),
+        ))),
+        amount => Ok(amount),
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TxType {
-    #[serde(alias = "deposit")]
+    #[serde(rename(serialize = "deposit"), alias = "deposit")]
     Deposit,
-    #[serde(alias = "withdrawal")]
+    #[serde(rename(serialize = "withdrawal"), alias = "withdrawal")]
     Withdrawal,
-    #[serde(alias = "dispute")]
+    #[serde(rename(serialize = "dispute"), alias = "dispute")]
     Dispute,
-    #[serde(alias = "resolve")]
+    #[serde(rename(serialize = "resolve"), alias = "resolve")]
     Resolve,
-    #[serde(alias = "chargeback")]
+    #[serde(rename(serialize = "chargeback"), alias = "chargeback")]
     ChargeBack,
 }
 
-pub fn process_csv(csv_path: &OsString) -> Result<Vec<TxRow>, Box<dyn Error>> {
-    let file = File::open(csv_path)?;
-    let mut rdr = csv::ReaderBuilder::new()
+#[derive(Serialize)]
+struct RejectionRow {
+    #[serde(rename = "type")]
+    tx_type: TxType,
+    client: u16,
+    tx: u32,
+    reason: &'static str,
+}
+
+/// Whether malformed input rows abort the run or are skipped and reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    Strict,
+    Lenient,
+}
+
+/// Input row which could not be read as a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    /// Input the row was read from, counting the inputs of a run from 1, or 0 when not known
+    pub input: usize,
+    /// Line within its input
+    pub line: u64,
+    pub column: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.input > 0 {
+            write!(f, "input {}, ", self.input)?;
+        }
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl Error for RowError {}
+
+/// Failure to read the input, either a single malformed row or the input as a whole
+#[derive(Debug)]
+pub enum InputError {
+    /// Row which is not valid csv, at `byte` offset into the input
+    Syntax {
+        error: RowError,
+        byte: u64,
+    },
+    /// Row which does not describe a transaction
+    Schema(RowError),
+    /// Row whose amount could not be read
+    Amount(RowError),
+    /// Header row does not contain the expected columns
+    Header(String),
+    Io(csv::Error),
+    /// Failure reading a non-csv input
+    Read(io::Error),
+}
+
+impl InputError {
+    /// Names `input` as the one a malformed row was read from
+    pub fn in_input(mut self, input: usize) -> Self {
+        if let InputError::Syntax { error, .. }
+        | InputError::Schema(error)
+        | InputError::Amount(error) = &mut self
+        {
+            error.input = input;
+        }
+        self
+    }
+}
+
+impl FromStr for TxType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" | "Deposit" => Ok(TxType::Deposit),
+            "withdrawal" | "Withdrawal" => Ok(TxType::Withdrawal),
+            "dispute" | "Dispute" => Ok(TxType::Dispute),
+            "resolve" | "Resolve" => Ok(TxType::Resolve),
+            "chargeback" | "ChargeBack" => Ok(TxType::ChargeBack),
+            _ => Err(format!("unknown transaction type '{}'", s)),
+        }
+    }
+}
+
+/// Position of each transaction field within a record, taken from the header row
+struct Columns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+}
+
+impl Columns {
+    fn from_headers(headers: &csv::StringRecord) -> Result<Self, InputError> {
+        let find = |name: &str| headers.iter().position(|h| h == name);
+        let require = |name: &str| {
+            find(name).ok_or_else(|| InputError::Header(format!("missing '{}' column", name)))
+        };
+        Ok(Columns {
+            tx_type: require("type")?,
+            client: require("client")?,
+            tx: require("tx")?,
+            amount: find("amount"),
+        })
+    }
+}
+
+fn malformed(line: u64, field: usize, message: String) -> InputError {
+    InputError::Schema(RowError {
+        input: 0,
+        line,
+        column: Some(field as u64 + 1),
+        message,
+    })
+}
+
+fn parse_field<T: FromStr>(
+    record: &csv::StringRecord,
+    line: u64,
+    field: usize,
+    what: &str,
+) -> Result<T, InputError> {
+    let value = record.get(field).unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| malformed(line, field, format!("invalid {} '{}'", what, value)))
+}
+
+/// Lazily parses transaction rows from a csv reader, one row at a time.
+///
+/// Fields are parsed individually so that errors can point at the offending column.
+pub struct CsvRows<R> {
+    rdr: csv::Reader<R>,
+    columns: Option<Columns>,
+    record: csv::StringRecord,
+}
+
+impl<R: Read> CsvRows<R> {
+    /// Line the last row read started on
+    pub fn line(&self) -> u64 {
+        self.record.position().map_or(0, |p| p.line())
+    }
+
+    fn parse_record(&mut self) -> Result<TxRow, InputError> {
+        if self.columns.is_none() {
+            let headers = self.rdr.headers().map_err(InputError::Io)?;
+            self.columns = Some(Columns::from_headers(headers)?);
+        }
+        let columns = self.columns.as_ref().unwrap();
+        let record = &self.record;
+        let line = record.position().map_or(0, |p| p.line());
+
+        let field = |i: usize| record.get(i).unwrap_or_default();
+
+        let tx_type: TxType = field(columns.tx_type)
+            .parse()
+            .map_err(|e| malformed(line, columns.tx_type, e))?;
+        let client = parse_field(record, line, columns.client, "client id")?;
+        let tx = parse_field(record, line, columns.tx, "transaction id")?;
+        let amount = match columns.amount.filter(|&i| !field(i).is_empty()) {
+            Some(i) => Some(Amount::parse_positive(field(i)).map_err(|e| {
+                InputError::Amount(RowError {
+                    input: 0,
+                    line,
+                    column: Some(i as u64 + 1),
+                    message: e.to_string(),
+                })
+            })?),
+            None => None,
+        };
+
+        // deposits and withdrawals are meaningless without an amount
+        if let (TxType::Deposit | TxType::Withdrawal, None) = (tx_type, amount) {
+            return Err(InputError::Schema(RowError {
+                input: 0,
+                line,
+                column: columns.amount.map(|i| i as u64 + 1),
+                message: "missing amount".to_string(),
+            }));
+        }
+
+        Ok(TxRow {
+            tx_type,
+            client,
+            tx,
+            amount,
+        })
+    }
+
+    /// Locates csv errors within the input. I/O failures are not tied to a row.
+    fn read_error(&self, e: csv::Error) -> InputError {
+        let line = |pos: &Option<csv::Position>| {
+            pos.as_ref()
+                .map_or(self.rdr.position().line(), |p| p.line())
+        };
+        let byte = e
+            .position()
+            .map_or(self.rdr.position().byte(), |p| p.byte());
+        let row_error = match e.kind() {
+            csv::ErrorKind::Utf8 { pos, err } => RowError {
+                input: 0,
+                line: line(pos),
+                column: Some(err.field() as u64 + 1),
+                message: "invalid utf-8".to_string(),
+            },
+            csv::ErrorKind::UnequalLengths {
+                pos,
+                expected_len,
+                len,
+            } => RowError {
+                input: 0,
+                line: line(pos),
+                column: None,
+                message: format!("expected {} fields, found {}", expected_len, len),
+            },
+            _ => return InputError::Io(e),
+        };
+        InputError::Syntax {
+            error: row_error,
+            byte,
+        }
+    }
+}
+
+impl<R: Read> Iterator for CsvRows<R> {
+    type Item = Result<TxRow, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rdr.read_record(&mut self.record) {
+            Ok(true) => Some(self.parse_record()),
+            Ok(false) => None,
+            Err(e) => Some(Err(self.read_error(e))),
+        }
+    }
+}
+
+pub fn process_csv<R: Read>(reader: R) -> CsvRows<R> {
+    let rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    CsvRows {
+        rdr,
+        columns: None,
+        record: csv::StringRecord::new(),
+    }
+}
+
+/// Parses a single headerless row with the columns in their usual `type,client,tx,amount`
+/// order, as sent over a live connection
+pub fn parse_csv_line(line: &str) -> Result<TxRow, InputError> {
+    let rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
         .trim(csv::Trim::All)
-        .from_reader(file);
+        .from_reader(line.as_bytes());
+    let mut rows = CsvRows {
+        rdr,
+        columns: Some(Columns {
+            tx_type: 0,
+            client: 1,
+            tx: 2,
+            amount: Some(3),
+        }),
+        record: csv::StringRecord::new(),
+    };
+    rows.next().unwrap_or_else(|| {
+        Err(InputError::Schema(RowError {
+            input: 0,
+            line: 1,
+            column: None,
+            message: "empty row".to_string(),
+        }))
+    })
+}
+
+pub fn output_csv(accounts: &[Account]) -> Result<String, EngineError> {
+    let mut out = Vec::new();
+    write_accounts(accounts, &mut out, ReportFormat::Csv, false)?;
+    String::from_utf8(out).map_err(output_error)
+}
 
-    let mut rows: Vec<TxRow> = Vec::new();
-    for result in rdr.deserialize() {
-        let tx_row: TxRow = result?;
-        rows.push(tx_row);
+/// A single client's row of the accounts report, without the header
+pub fn output_client_csv(client: &Client) -> Result<String, EngineError> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    wtr.serialize(client).map_err(output_error)?;
+    into_string(wtr)
+}
+
+pub fn output_rejections_csv(rejections: &[Rejection]) -> Result<String, EngineError> {
+    // header is written up front so that a run without rejections still yields a valid report
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    wtr.write_record(["type", "client", "tx", "reason"])
+        .map_err(output_error)?;
+
+    for rejection in rejections {
+        wtr.serialize(RejectionRow {
+            tx_type: rejection.tx_type,
+            client: rejection.client,
+            tx: rejection.tx,
+            reason: rejection.error.code(),
+        })
+        .map_err(output_error)?
     }
 
-    Ok(rows)
+    into_string(wtr)
 }
-pub fn output_csv(clients: &Vec<&Client>) -> Result<String, Box<dyn Error>> {
-    let mut wtr = csv::Writer::from_writer(vec![]);
 
-    for client in clients {
-        wtr.serialize(client)?
+pub fn output_row_errors_csv(errors: &[RowError]) -> Result<String, EngineError> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    wtr.write_record(["input", "line", "column", "error"])
+        .map_err(output_error)?;
+
+    for error in errors {
+        let column = error.column.map(|c| c.to_string()).unwrap_or_default();
+        let record = [
+            &error.input.to_string(),
+            &error.line.to_string(),
+            &column,
+            &error.message,
+        ];
+        wtr.write_record(record).map_err(output_error)?;
     }
 
-    wtr.flush()?;
-    let data = String::from_utf8(wtr.into_inner()?)?;
-    Ok(data)
+    into_string(wtr)
+}
+
+fn output_error(e: impl fmt::Display) -> EngineError {
+    EngineError::Output(e.to_string())
+}
+
+fn into_string(wtr: csv::Writer<Vec<u8>>) -> Result<String, EngineError> {
+    let data = wtr.into_inner().map_err(output_error)?;
+    String::from_utf8(data).map_err(output_error)
 }
@@ -0,0 +1,108 @@
+use std::{io::Read, path::Path};
+
+use super::csv_io::{process_csv, CsvRows, InputError, ParseMode, RowError, TxRow};
+use super::jsonl_io::{process_jsonl, JsonlRows};
+
+/// Layout of a transaction input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Csv with a `type,client,tx,amount` header row
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl InputFormat {
+    /// Format suggested by a file name, looking past a compression extension such as
+    /// `transactions.jsonl.gz`. `None` when the name does not tell.
+    pub fn from_path(path: &Path) -> Option<InputFormat> {
+        let name = match path.extension().and_then(|e| e.to_str()) {
+            Some("gz" | "zst" | "zstd") => Path::new(path.file_stem()?),
+            _ => path,
+        };
+        match name.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Some(InputFormat::Csv),
+            Some("jsonl" | "ndjson") => Some(InputFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// Transaction rows read from an input in either format
+pub enum TxRows<R> {
+    Csv(CsvRows<R>),
+    Jsonl(JsonlRows<R>),
+}
+
+impl<R: Read> TxRows<R> {
+    /// Line the last row read was on, counting from 1 at the start of the input
+    pub fn line(&self) -> u64 {
+        match self {
+            TxRows::Csv(rows) => rows.line(),
+            TxRows::Jsonl(rows) => rows.line(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for TxRows<R> {
+    type Item = Result<TxRow, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            TxRows::Csv(rows) => rows.next(),
+            TxRows::Jsonl(rows) => rows.next(),
+        }
+    }
+}
+
+pub fn read_rows<R: Read>(reader: R, format: InputFormat) -> TxRows<R> {
+    match format {
+        InputFormat::Csv => TxRows::Csv(process_csv(reader)),
+        InputFormat::Jsonl => TxRows::Jsonl(process_jsonl(reader)),
+    }
+}
+
+/// Row read from an input, once sorted by [`classify_row`]
+#[derive(Debug)]
+pub enum RowOutcome {
+    Valid(TxRow),
+    /// Malformed row which is skipped and reported
+    Malformed(RowError),
+}
+
+/// Sorts a row read from `input`, counting the inputs of a run from 1, into one to apply or a
+/// malformed one to skip. Malformed rows are an error in strict mode, as are failures affecting
+/// a whole input in either mode. Either way the row's error names its input.
+pub fn classify_row(
+    row: Result<TxRow, InputError>,
+    input: usize,
+    mode: ParseMode,
+) -> Result<RowOutcome, InputError> {
+    match row.map_err(|e| e.in_input(input)) {
+        Ok(row) => Ok(RowOutcome::Valid(row)),
+        Err(
+            InputError::Syntax { error, .. }
+            | InputError::Schema(error)
+            | InputError::Amount(error),
+        ) if mode == ParseMode::Lenient => Ok(RowOutcome::Malformed(error)),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::InputFormat;
+
+    #[test]
+    fn format_is_taken_from_extension() {
+        let from = |p: &str| InputFormat::from_path(Path::new(p));
+        assert_eq!(from("day1.csv"), Some(InputFormat::Csv));
+        assert_eq!(from("day1.jsonl"), Some(InputFormat::Jsonl));
+        assert_eq!(from("day1.ndjson.gz"), Some(InputFormat::Jsonl));
+        assert_eq!(from("day1.csv.zst"), Some(InputFormat::Csv));
+        assert_eq!(from("-"), None);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    str,
+};
+
+use serde_json::{Map, Value};
+
+use crate::amount_mod::amount::{Amount, AmountError};
+
+use super::csv_io::{InputError, RowError, TxRow, TxType};
+
+/// Lazily parses transactions from newline-delimited JSON, one object per line, e.g.
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+///
+/// Fields are checked individually, like csv rows, so that errors name the offending field.
+/// Blank lines are skipped but still counted.
+pub struct JsonlRows<R> {
+    reader: BufReader<R>,
+    buf: Vec<u8>,
+    line: u64,
+    byte: u64,
+}
+
+impl<R: Read> Iterator for JsonlRows<R> {
+    type Item = Result<TxRow, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            let start = self.byte;
+            match self.reader.read_until(b'\n', &mut self.buf) {
+                Ok(0) => return None,
+                Ok(n) => self.byte += n as u64,
+                Err(e) => return Some(Err(InputError::Read(e))),
+            }
+            self.line += 1;
+
+            let Ok(text) = str::from_utf8(&self.buf) else {
+                return Some(Err(syntax(self.line, None, start, "invalid utf-8")));
+            };
+            if !text.trim().is_empty() {
+                return Some(parse_line(text.trim_end(), self.line, start));
+            }
+        }
+    }
+}
+
+impl<R> JsonlRows<R> {
+    /// Line the last row read was on
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+pub fn process_jsonl<R: Read>(reader: R) -> JsonlRows<R> {
+    JsonlRows {
+        reader: BufReader::new(reader),
+        buf: Vec::new(),
+        line: 0,
+        byte: 0,
+    }
+}
+
+fn parse_line(text: &str, line: u64, byte: u64) -> Result<TxRow, InputError> {
+    let value: Value = serde_json::from_str(text).map_err(|e| {
+        // the position is reported separately
+        let message = e.to_string();
+        let message = message
+            .rsplit_once(" at line ")
+            .map_or(&*message, |(m, _)| m);
+        syntax(line, Some(e.column() as u64), byte, message)
+    })?;
+    let Value::Object(fields) = value else {
+        return Err(schema(line, "expected a json object".to_string()));
+    };
+
+    let tx_type: TxType = match fields.get("type") {
+        Some(Value::String(s)) => s.parse().map_err(|e| schema(line, e))?,
+        Some(other) => {
+            return Err(schema(
+                line,
+                format!("unknown transaction type {}", quoted(other)),
+            ))
+        }
+        None => return Err(schema(line, "missing 'type' field".to_string())),
+    };
+    let client = id_field(&fields, "client", "client id", line)?;
+    let tx = id_field(&fields, "tx", "transaction id", line)?;
+    let amount = match fields.get("amount") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) if s.trim().is_empty() => None,
+        Some(Value::String(s)) => Some(parse_amount(s.trim(), line)?),
+        // numbers keep their json text with serde_json's arbitrary_precision, so are read
+        // exactly rather than through a float
+        Some(Value::Number(n)) => Some(parse_amount(&n.to_string(), line)?),
+        Some(other) => return Err(schema(line, format!("invalid amount {}", quoted(other)))),
+    };
+
+    // deposits and withdrawals are meaningless without an amount
+    if let (TxType::Deposit | TxType::Withdrawal, None) = (tx_type, amount) {
+        return Err(schema(line, "missing amount".to_string()));
+    }
+
+    Ok(TxRow {
+        tx_type,
+        client,
+        tx,
+        amount,
+    })
+}
+
+/// Reads a client or transaction id, given either as a number or a string of digits
+fn id_field<T: TryFrom<u64>>(
+    fields: &Map<String, Value>,
+    name: &str,
+    what: &str,
+    line: u64,
+) -> Result<T, InputError> {
+    let value = fields
+        .get(name)
+        .ok_or_else(|| schema(line, format!("missing '{}' field", name)))?;
+    let id = match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+    id.and_then(|id| T::try_from(id).ok())
+        .ok_or_else(|| schema(line, format!("invalid {} {}", what, quoted(value))))
+}
+
+/// Field value as quoted in error messages, matching the csv reader's
+fn quoted(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("'{}'", s),
+        other => format!("'{}'", other),
+    }
+}
+
+fn parse_amount(text: &str, line: u64) -> Result<Amount, InputError> {
+    Amount::parse_positive(text).map_err(|e: AmountError| {
+        InputError::Amount(RowError {
+            input: 0,
+            line,
+            column: None,
+            message: e.to_string(),
+        })
+    })
+}
+
+fn schema(line: u64, message: String) -> InputError {
+    InputError::Schema(RowError {
+        input: 0,
+        line,
+        column: None,
+        message,
+    })
+}
+
+fn syntax(line: u64, column: Option<u64>, byte: u64, message: &str) -> InputError {
+    InputError::Syntax {
+        error: RowError {
+            input: 0,
+            line,
+            column,
+            message: message.to_string(),
+        },
+        byte,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::process_jsonl;
+    use crate::io_mod::csv_io::{InputError, TxType};
+
+    #[test]
+    fn amounts_may_be_strings_or_numbers() {
+        let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1.5\"}\n\
+                     \n\
+                     {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": 0.25}\n\
+                     {\"type\": \"dispute\", \"client\": 1, \"tx\": 1}";
+        let rows: Vec<_> = process_jsonl(input.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].amount, Some("1.5".parse().unwrap()));
+        assert_eq!(rows[1].amount, Some("0.25".parse().unwrap()));
+        assert_eq!(rows[2].tx_type, TxType::Dispute);
+        assert_eq!(rows[2].amount, None);
+    }
+
+    #[test]
+    fn numeric_amounts_keep_every_digit() {
+        let input =
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1234567890123.4567}";
+        let rows: Vec<_> = process_jsonl(input.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows[0].amount, Some("1234567890123.4567".parse().unwrap()));
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1\"}\n\
+                     {\"type\": \"deposit\", \"client\": 1,\n\
+                     {\"type\": \"refund\", \"client\": 1, \"tx\": 2}\n\
+                     {\"type\": \"deposit\", \"client\": 70000, \"tx\": 3, \"amount\": \"1\"}\n\
+                     {\"type\": \"deposit\", \"client\": 1, \"tx\": 4, \"amount\": \"1.00001\"}\n\
+                     {\"type\": \"withdrawal\", \"client\": 1, \"tx\": 5, \"amount\": -1}\n";
+        let errors: Vec<String> = process_jsonl(input.as_bytes())
+            .filter_map(|row| match row {
+                Ok(_) => None,
+                Err(InputError::Syntax { error, byte }) => Some(format!("{} @{}", error, byte)),
+                Err(InputError::Schema(e) | InputError::Amount(e)) => Some(e.to_string()),
+                Err(e) => panic!("unexpected error {:?}", e),
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "line 2, column 32: EOF while parsing a value @57",
+                "line 3: unknown transaction type 'refund'",
+                "line 4: invalid client id '70000'",
+                "line 5: amount '1.00001' has more than 4 decimal places",
+                "line 6: amount '-1' is not positive",
+            ]
+        );
+    }
+}
@@ -1 +1,5 @@
+pub mod compression;
 pub mod csv_io;
+pub mod input;
+pub mod jsonl_io;
+pub mod report;
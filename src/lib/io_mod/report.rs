@@ -0,0 +1,237 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::amount_mod::amount::Amount;
+use crate::client_mod::client::Account;
+use crate::client_mod::history::HistoryEntry;
+use crate::error_mod::error::EngineError;
+use crate::io_mod::csv_io::TxType;
+
+/// Layout of the accounts report and client statements
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Csv,
+    /// A single JSON array of objects
+    Json,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Account as written to the report. Amounts serialize as exact decimal strings in every format,
+/// and the detail fields are only present when asked for.
+#[derive(Serialize)]
+struct AccountRow {
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open_disputes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_withdrawals: Option<Amount>,
+}
+
+#[derive(Serialize)]
+struct StatementRow {
+    #[serde(rename = "type")]
+    tx_type: TxType,
+    tx: u32,
+    amount: Option<Amount>,
+    status: &'static str,
+    reason: Option<&'static str>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+impl AccountRow {
+    fn new(account: &Account, details: bool) -> Self {
+        Self {
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            open_disputes: details.then_some(account.open_disputes),
+            pending_withdrawals: details.then_some(account.pending_withdrawals),
+        }
+    }
+}
+
+/// Writes the accounts report to `out` as it is serialized, in the order given. With `details`,
+/// each account also lists its open disputes and pending withdrawals.
+pub fn write_accounts<W: Write>(
+    accounts: &[Account],
+    out: W,
+    format: ReportFormat,
+    details: bool,
+) -> Result<(), EngineError> {
+    let rows = accounts.iter().map(|a| AccountRow::new(a, details));
+    let mut header = vec!["client", "available", "held", "total", "locked"];
+    if details {
+        header.extend(["open_disputes", "pending_withdrawals"]);
+    }
+    write_rows(rows, &header, out, format)
+}
+
+/// Writes a client's statement: every transaction it sent, in order, with whether it was
+/// applied and the balances it left the account with
+pub fn write_statement<W: Write>(
+    history: &[HistoryEntry],
+    out: W,
+    format: ReportFormat,
+) -> Result<(), EngineError> {
+    let rows = history.iter().map(|entry| StatementRow {
+        tx_type: entry.tx_type,
+        tx: entry.tx,
+        amount: entry.amount,
+        status: entry.status(),
+        reason: entry.result.err().map(|e| e.code()),
+        available: entry.available,
+        held: entry.held,
+        total: entry.total,
+        locked: entry.locked,
+    });
+    let header = [
+        "type",
+        "tx",
+        "amount",
+        "status",
+        "reason",
+        "available",
+        "held",
+        "total",
+        "locked",
+    ];
+    write_rows(rows, &header, out, format)
+}
+
+fn write_rows<T: Serialize, W: Write>(
+    rows: impl Iterator<Item = T>,
+    header: &[&str],
+    out: W,
+    format: ReportFormat,
+) -> Result<(), EngineError> {
+    match format {
+        ReportFormat::Csv => write_csv(rows, header, out),
+        ReportFormat::Json => write_json(rows, out),
+        ReportFormat::Jsonl => write_jsonl(rows, out),
+    }
+}
+
+fn write_csv<T: Serialize, W: Write>(
+    rows: impl Iterator<Item = T>,
+    header: &[&str],
+    out: W,
+) -> Result<(), EngineError> {
+    // header is written up front so that a report without rows is still valid
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(out);
+    wtr.write_record(header).map_err(csv_error)?;
+
+    for row in rows {
+        wtr.serialize(row).map_err(csv_error)?
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+fn write_json<T: Serialize, W: Write>(
+    rows: impl Iterator<Item = T>,
+    mut out: W,
+) -> Result<(), EngineError> {
+    out.write_all(b"[")?;
+    for (i, row) in rows.enumerate() {
+        out.write_all(if i == 0 { b"\n" } else { b",\n" })?;
+        serde_json::to_writer(&mut out, &row).map_err(json_error)?;
+    }
+    out.write_all(b"\n]\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_jsonl<T: Serialize, W: Write>(
+    rows: impl Iterator<Item = T>,
+    mut out: W,
+) -> Result<(), EngineError> {
+    for row in rows {
+        serde_json::to_writer(&mut out, &row).map_err(json_error)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Failures of the destination are reported as i/o errors, anything else as output errors
+fn csv_error(e: csv::Error) -> EngineError {
+    if e.is_io_error() {
+        EngineError::Io(e.into())
+    } else {
+        EngineError::Output(e.to_string())
+    }
+}
+
+fn json_error(e: serde_json::Error) -> EngineError {
+    if e.is_io() {
+        EngineError::Io(e.into())
+    } else {
+        EngineError::Output(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_accounts, ReportFormat};
+    use crate::client_mod::client::Account;
+
+    fn account(client: u16, total: &str, open_disputes: usize) -> Account {
+        Account {
+            client,
+            available: total.parse().unwrap(),
+            held: "0".parse().unwrap(),
+            total: total.parse().unwrap(),
+            locked: false,
+            open_disputes,
+            pending_withdrawals: "0.5".parse().unwrap(),
+        }
+    }
+
+    fn written(format: ReportFormat, details: bool) -> String {
+        let accounts = [account(1, "1.5", 0), account(2, "0.0001", 1)];
+        let mut out = Vec::new();
+        write_accounts(&accounts, &mut out, format, details).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_reports_use_decimal_strings() {
+        assert_eq!(
+            written(ReportFormat::Json, false),
+            "[\n\
+             {\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false},\n\
+             {\"client\":2,\"available\":\"0.0001\",\"held\":\"0.0000\",\"total\":\"0.0001\",\"locked\":false}\n\
+             ]\n"
+        );
+        assert_eq!(
+            written(ReportFormat::Jsonl, true).lines().nth(1).unwrap(),
+            "{\"client\":2,\"available\":\"0.0001\",\"held\":\"0.0000\",\"total\":\"0.0001\",\
+             \"locked\":false,\"open_disputes\":1,\"pending_withdrawals\":\"0.5000\"}"
+        );
+    }
+
+    #[test]
+    fn csv_details_add_columns() {
+        assert_eq!(
+            written(ReportFormat::Csv, true),
+            "client,available,held,total,locked,open_disputes,pending_withdrawals\n\
+             1,1.5000,0.0000,1.5000,false,0,0.5000\n\
+             2,0.0001,0.0000,0.0001,false,1,0.5000\n"
+        );
+    }
+}
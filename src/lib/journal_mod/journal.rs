@@ -0,0 +1,189 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit_mod::audit::Source;
+use crate::io_mod::csv_io::TxRow;
+
+/// Row handed to the processor, numbered by its position among the well-formed input rows, and
+/// with where it was read from so that a rerun can check it is reading the same input
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalRecord {
+    seq: u64,
+    row: TxRow,
+    /// Absent from records written before inputs were checked
+    #[serde(default)]
+    input: usize,
+    #[serde(default)]
+    line: u64,
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    /// Record failed its checksum or could not be decoded, and is followed by further records
+    Corrupt {
+        line: u64,
+    },
+    /// Input row differs from the journaled row it would be skipped as, so the journal is left
+    /// over from another run
+    Mismatch {
+        input: usize,
+        line: u64,
+    },
+    /// Inputs ended before every journaled row was seen again
+    Unmatched {
+        rows: usize,
+    },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(e) => write!(f, "could not access journal: {}", e),
+            JournalError::Corrupt { line } => write!(f, "journal record {} is corrupt", line),
+            JournalError::Mismatch { input, line } => write!(
+                f,
+                "input {} line {} does not match the journal, which may be left over from \
+                 another run",
+                input, line
+            ),
+            JournalError::Unmatched { rows } => write!(
+                f,
+                "inputs ended before {} journaled rows, which may be left over from another run",
+                rows
+            ),
+        }
+    }
+}
+
+impl Error for JournalError {}
+
+impl From<io::Error> for JournalError {
+    fn from(e: io::Error) -> Self {
+        JournalError::Io(e)
+    }
+}
+
+/// Append-only write-ahead log of the rows applied to the processor.
+///
+/// Each row is written and flushed before it is applied, as one line holding a crc32 checksum
+/// followed by the record as json. Rows which end up rejected are journaled too, as replaying
+/// them reproduces the same rejection. A crash can at worst leave a torn final record, which is
+/// discarded on recovery.
+pub struct Journal {
+    wtr: BufWriter<File>,
+    next_seq: u64,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if needed, and returns it along with the rows
+    /// already recorded, and where each was read from, so they can be replayed
+    pub fn recover(path: &Path) -> Result<(Journal, Vec<(Source, TxRow)>), JournalError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let (rows, valid_len) = read_records(BufReader::new(&file))?;
+        // drop a torn record left by a crash mid-write, so new records start on a clean line
+        file.set_len(valid_len)?;
+
+        let mut wtr = BufWriter::new(file);
+        io::Seek::seek(&mut wtr, io::SeekFrom::End(0))?;
+        let journal = Journal {
+            wtr,
+            next_seq: rows.len() as u64,
+        };
+        Ok((journal, rows))
+    }
+
+    /// Records `row`, read from `source`, ahead of it being applied
+    pub fn append(&mut self, row: &TxRow, source: Source) -> Result<(), JournalError> {
+        let record = JournalRecord {
+            seq: self.next_seq,
+            row: row.clone(),
+            input: source.input,
+            line: source.line,
+        };
+        let json = serde_json::to_string(&record).map_err(io::Error::from)?;
+        writeln!(
+            self.wtr,
+            "{:08x} {}",
+            crc32fast::hash(json.as_bytes()),
+            json
+        )?;
+        self.wtr.flush()?;
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Forces journaled records to disk
+    pub fn sync(&mut self) -> Result<(), JournalError> {
+        self.wtr.flush()?;
+        self.wtr.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// Empties the journal once its rows are covered by a saved snapshot
+    pub fn checkpoint(&mut self) -> Result<(), JournalError> {
+        self.wtr.flush()?;
+        let file = self.wtr.get_mut();
+        file.set_len(0)?;
+        io::Seek::seek(file, io::SeekFrom::Start(0))?;
+        file.sync_data()?;
+        self.next_seq = 0;
+        Ok(())
+    }
+}
+
+/// Reads every intact record, returning them with the byte length they occupy. Only the final
+/// record may be damaged, as records are written strictly in order.
+fn read_records<R: BufRead>(mut rdr: R) -> Result<(Vec<(Source, TxRow)>, u64), JournalError> {
+    let mut rows = Vec::new();
+    let mut valid_len = 0;
+    let mut torn = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let n = rdr.read_line(&mut line)?;
+        if n == 0 {
+            break;
+        }
+        if let Some(line) = torn {
+            return Err(JournalError::Corrupt { line });
+        }
+
+        match decode(&line, rows.len() as u64) {
+            Some(row) if line.ends_with('\n') => {
+                rows.push(row);
+                valid_len += n as u64;
+            }
+            _ => torn = Some(rows.len() as u64 + 1),
+        }
+    }
+    Ok((rows, valid_len))
+}
+
+fn decode(line: &str, expected_seq: u64) -> Option<(Source, TxRow)> {
+    let (checksum, json) = line.trim_end_matches('\n').split_once(' ')?;
+    let checksum = u32::from_str_radix(checksum, 16).ok()?;
+    if crc32fast::hash(json.as_bytes()) != checksum {
+        return None;
+    }
+    let record: JournalRecord = serde_json::from_str(json).ok()?;
+    let source = Source {
+        input: record.input,
+        line: record.line,
+    };
+    (record.seq == expected_seq).then_some((source, record.row))
+}
@@ -1,19 +1,428 @@
-use std::{error::Error, ffi::OsString};
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    io::{Read, Write},
+    iter,
+    net::TcpListener,
+    path::PathBuf,
+    sync::Arc,
+};
 
+use audit_mod::audit::{AuditLog, Source};
 use csv_processor_mod::csv_processor::CsvProcessor;
-use io_mod::csv_io::{output_csv, process_csv};
+use csv_processor_mod::sharded_processor::ShardedProcessor;
+use io_mod::csv_io::{output_csv, output_rejections_csv, output_row_errors_csv};
+use io_mod::input::{classify_row, read_rows, RowOutcome};
+use io_mod::report::write_accounts;
+use journal_mod::journal::Journal;
+use server_mod::server::serve;
+use snapshot_mod::snapshot::{load_snapshot, save_snapshot};
 
+mod amount_mod;
+#[cfg(feature = "async")]
+mod async_mod;
+mod audit_mod;
 mod client_mod;
 mod csv_processor_mod;
+mod engine_mod;
+mod error_mod;
 mod io_mod;
+mod journal_mod;
+mod server_mod;
+mod snapshot_mod;
 
-pub fn process_payments(csv_path: &OsString) -> Result<String, Box<dyn Error>> {
-    let rows = process_csv(csv_path)?;
+pub use amount_mod::amount::{Amount, AmountError};
+#[cfg(feature = "async")]
+pub use async_mod::ingest::{process_streams, STREAM_BUFFER};
+pub use audit_mod::audit::{replay_audit, AuditError, AuditEvent, AuditOp, Balances};
+pub use client_mod::client::{Account, ClientTx, Tx};
+pub use client_mod::dispute_policy::{DepositsOnly, DisputePolicy, Movement, WithdrawalReversal};
+pub use client_mod::history::HistoryEntry;
+pub use client_mod::outcome::{Applied, TxError};
+pub use csv_processor_mod::csv_processor::{AccountOrder, Rejection};
+pub use engine_mod::engine::Engine;
+pub use error_mod::error::EngineError;
+pub use io_mod::compression::{
+    decompress, open_input, write_compressed, CompressedWriter, Compression,
+};
+pub use io_mod::csv_io::{ParseMode, RowError, TxRow, TxType};
+pub use io_mod::input::InputFormat;
+pub use io_mod::report::{write_statement, ReportFormat};
+pub use journal_mod::journal::JournalError;
+pub use snapshot_mod::snapshot::{SnapshotError, SNAPSHOT_VERSION};
 
-    let mut row_processor = CsvProcessor::new();
-    row_processor.process_rows(&rows);
-    let clients = row_processor.client_results();
+/// Settings for a processing run
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub mode: ParseMode,
+    /// Format of every input
+    pub input_format: InputFormat,
+    pub dispute_policy: Arc<dyn DisputePolicy>,
+    /// Snapshot to resume account state from, instead of starting with no clients
+    pub state_in: Option<PathBuf>,
+    /// Where to save the account state once all rows have been processed
+    pub state_out: Option<PathBuf>,
+    /// Write-ahead journal making the run crash-safe. Rows recorded by an interrupted run are
+    /// replayed on top of `state_in`, and the input rows they were read from are skipped. Fails
+    /// if those rows do not match the journal. The journal is emptied once `state_out` has been
+    /// saved.
+    pub journal: Option<PathBuf>,
+    /// Worker threads to spread clients across. With 1, rows are applied on the calling thread.
+    pub threads: usize,
+    /// Order of the accounts report
+    pub order: AccountOrder,
+    /// Keep every rejected row for [`PaymentsReport::rejections`]. Rejections grow with the
+    /// input, so they are only kept when asked for. Flagged rejections are always counted.
+    pub keep_rejections: bool,
+    /// Keep every client's transactions, with the balances each resulted in, for statements.
+    /// Histories grow with the input and are saved in snapshots.
+    pub history: bool,
+    /// JSON Lines file receiving an audit event for every change to an account's balances,
+    /// from which the accounts can be rebuilt with [`replay_audit`]
+    pub audit: Option<PathBuf>,
+    /// Stops reading the inputs at this point, leaving accounts as they were just before it.
+    /// Cannot be combined with `state_out`, `journal` or `audit`, which would record a run that
+    /// never finished.
+    pub stop_at: Option<StopAt>,
+}
+
+/// Point in the inputs to stop processing at, for looking at accounts as of an earlier moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopAt {
+    /// Before the data row with this number, counting every row of every input from 1,
+    /// including malformed rows but not header rows
+    Row(u64),
+    /// Before the first row with this transaction id
+    Tx(u32),
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            mode: ParseMode::Strict,
+            input_format: InputFormat::Csv,
+            dispute_policy: Arc::new(DepositsOnly),
+            state_in: None,
+            state_out: None,
+            journal: None,
+            threads: 1,
+            order: AccountOrder::ClientId,
+            keep_rejections: false,
+            history: false,
+            audit: None,
+            stop_at: None,
+        }
+    }
+}
+
+/// Account report produced by a run, together with every input row that had no effect
+#[derive(Debug)]
+pub struct PaymentsReport {
+    /// Final balances, in the order asked for by [`Options::order`]
+    pub accounts: Vec<Account>,
+    /// Rows which had no effect, empty unless [`Options::keep_rejections`] was set
+    pub rejections: Vec<Rejection>,
+    /// Number of rejections flagged as possible fraud, whether or not they were kept
+    pub flagged_count: u64,
+    /// Rows skipped in lenient mode because they could not be read
+    pub malformed: Vec<RowError>,
+    /// Whether processing stopped at [`Options::stop_at`] before the end of the inputs
+    pub stopped_early: bool,
+}
+
+impl PaymentsReport {
+    /// Accounts report as csv, with a header row
+    pub fn accounts_csv(&self) -> Result<String, EngineError> {
+        output_csv(&self.accounts)
+    }
+
+    /// Streams the accounts report as csv to `out`, such as a file or stdout
+    pub fn write_accounts_csv<W: Write>(&self, out: W) -> Result<(), EngineError> {
+        self.write_accounts(out, ReportFormat::Csv, false)
+    }
+
+    /// Streams the accounts report to `out` in any format. With `details`, each account also
+    /// lists its open disputes and pending withdrawals.
+    pub fn write_accounts<W: Write>(
+        &self,
+        out: W,
+        format: ReportFormat,
+        details: bool,
+    ) -> Result<(), EngineError> {
+        write_accounts(&self.accounts, out, format, details)
+    }
+
+    /// Rejected rows as csv, with their type, client, tx and reason code
+    pub fn rejections_csv(&self) -> Result<String, EngineError> {
+        output_rejections_csv(&self.rejections)
+    }
+
+    /// Rejections flagged as possible fraud, such as disputes on another client's transaction
+    pub fn flagged(&self) -> impl Iterator<Item = &Rejection> {
+        self.rejections.iter().filter(|r| r.error.is_flagged())
+    }
+
+    /// Flagged rejections as csv, in the same layout as the rejections report
+    pub fn flagged_csv(&self) -> Result<String, EngineError> {
+        let flagged: Vec<Rejection> = self.flagged().cloned().collect();
+        output_rejections_csv(&flagged)
+    }
+
+    /// Skipped malformed rows as csv, with their line, column and error
+    pub fn malformed_csv(&self) -> Result<String, EngineError> {
+        output_row_errors_csv(&self.malformed)
+    }
+}
+
+/// Processes a csv or JSON Lines file, which may be gzip or zstd compressed. The format is taken
+/// from the extension, defaulting to csv.
+pub fn process_payments(path: &OsString) -> Result<String, EngineError> {
+    let file = open_input(path)?;
+    let options = Options {
+        input_format: InputFormat::from_path(path.as_ref()).unwrap_or_default(),
+        ..Options::default()
+    };
+    process_payments_report(file, options)?.accounts_csv()
+}
+
+/// Processes transactions streamed from any reader, holding only account state in memory
+pub fn process_payments_from_reader<R: Read>(reader: R) -> Result<String, EngineError> {
+    process_payments_report(reader, Options::default())?.accounts_csv()
+}
+
+pub fn process_payments_report<R: Read>(
+    reader: R,
+    options: Options,
+) -> Result<PaymentsReport, EngineError> {
+    process_payments_inputs(iter::once(reader), options)
+}
+
+/// Processes several inputs in order as one stream of transactions, sharing account state.
+/// Each csv input has its own header row, and line numbers in errors count from the start of the
+/// input they were found in.
+pub fn process_payments_inputs<R: Read>(
+    inputs: impl IntoIterator<Item = R>,
+    options: Options,
+) -> Result<PaymentsReport, EngineError> {
+    // each of these would record a run which never finished
+    let records_run =
+        options.state_out.is_some() || options.journal.is_some() || options.audit.is_some();
+    if options.stop_at.is_some() && records_run {
+        return Err(EngineError::Options(
+            "stop_at cannot be combined with state_out, journal or audit".to_string(),
+        ));
+    }
+
+    let mut row_processor = load_processor(&options)?;
+    let mut malformed = Vec::new();
+
+    let mut audit = None;
+    if let Some(path) = &options.audit {
+        row_processor.record_audit();
+        audit = Some(AuditLog::create(path)?);
+    }
+
+    let mut journal = None;
+    let mut already_applied = VecDeque::new();
+    if let Some(path) = &options.journal {
+        let (recovered, replay) = Journal::recover(path)?;
+        replay.iter().for_each(|(source, row)| {
+            let _ = row_processor.process_row_from(row, *source);
+        });
+        already_applied = replay.into();
+        journal = Some(recovered);
+    }
+
+    let mut runner = if options.threads > 1 {
+        Runner::Sharded(ShardedProcessor::new(row_processor, options.threads))
+    } else {
+        Runner::Single(row_processor)
+    };
+
+    let format = options.input_format;
+    let mut rows_read = 0;
+    let mut stopped_early = false;
+    'inputs: for (i, input) in inputs.into_iter().enumerate() {
+        let mut rows = read_rows(input, format);
+        while let Some(row) = rows.next() {
+            rows_read += 1;
+            stopped_early = match (options.stop_at, &row) {
+                (Some(StopAt::Row(n)), _) => rows_read >= n,
+                (Some(StopAt::Tx(tx)), Ok(row)) => row.tx == tx,
+                _ => false,
+            };
+            if stopped_early {
+                break 'inputs;
+            }
+            match classify_row(row, i + 1, options.mode)? {
+                RowOutcome::Valid(row) => {
+                    let source = Source {
+                        input: i + 1,
+                        line: rows.line(),
+                    };
+                    if let Some((journaled_source, journaled)) = already_applied.pop_front() {
+                        skip_journaled(journaled_source, &journaled, source, &row)?;
+                        continue;
+                    }
+                    if let Some(journal) = &mut journal {
+                        journal.append(&row, source)?;
+                    }
+                    runner.process_row(row, source);
+                    if let Some(audit) = &mut audit {
+                        audit.write(&runner.take_audit())?;
+                    }
+                }
+                RowOutcome::Malformed(error) => malformed.push(error),
+            }
+        }
+    }
+
+    if !already_applied.is_empty() {
+        let rows = already_applied.len();
+        return Err(JournalError::Unmatched { rows }.into());
+    }
+
+    let mut row_processor = runner.finish();
+    if let Some(mut audit) = audit {
+        audit.write(&row_processor.take_audit())?;
+        audit.finish()?;
+    }
+    if let Some(journal) = &mut journal {
+        journal.sync()?;
+    }
+    if let Some(path) = &options.state_out {
+        save_snapshot(path, &row_processor.snapshot())?;
+        if let Some(journal) = &mut journal {
+            journal.checkpoint()?;
+        }
+    }
+    let mut report = build_report(&row_processor, malformed, options.order);
+    report.stopped_early = stopped_early;
+    Ok(report)
+}
+
+/// Checks that an input row being skipped because it was journaled by an interrupted run is the
+/// row that was journaled. Records written before sources were journaled only have their row
+/// compared.
+fn skip_journaled(
+    journaled_source: Source,
+    journaled: &TxRow,
+    source: Source,
+    row: &TxRow,
+) -> Result<(), JournalError> {
+    let moved = journaled_source.input != 0 && journaled_source != source;
+    if moved || journaled != row {
+        return Err(JournalError::Mismatch {
+            input: source.input,
+            line: source.line,
+        });
+    }
+    Ok(())
+}
+
+/// Result of checking inputs without applying them
+#[derive(Debug)]
+pub struct Validation {
+    /// Rows which were read successfully
+    pub rows: u64,
+    pub malformed: Vec<RowError>,
+}
+
+/// Reads every row of the inputs, collecting the malformed ones, without applying any
+/// transaction. Failures affecting a whole input, such as a missing column, are still errors.
+pub fn validate_inputs<R: Read>(
+    inputs: impl IntoIterator<Item = R>,
+    format: InputFormat,
+) -> Result<Validation, EngineError> {
+    let mut validation = Validation {
+        rows: 0,
+        malformed: Vec::new(),
+    };
+    for (i, row) in inputs
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, input)| read_rows(input, format).map(move |row| (i, row)))
+    {
+        match classify_row(row, i + 1, ParseMode::Lenient)? {
+            RowOutcome::Valid(_) => validation.rows += 1,
+            RowOutcome::Malformed(error) => validation.malformed.push(error),
+        }
+    }
+    Ok(validation)
+}
+
+/// Applies rows sent over `listener` to a live engine and answers balance queries. Connections
+/// which cannot be accepted are skipped. Only `state_in` and `dispute_policy` are taken from `options`.
+pub fn serve_payments(listener: TcpListener, options: &Options) -> Result<(), EngineError> {
+    serve(listener, load_processor(options)?)?;
+    Ok(())
+}
+
+/// Serves a live engine over HTTP with a JSON API, until the listener fails. Only `state_in`
+/// and `dispute_policy` are taken from `options`.
+#[cfg(feature = "http")]
+pub fn serve_payments_http(listener: TcpListener, options: &Options) -> Result<(), EngineError> {
+    server_mod::http::serve_http(listener, load_processor(options)?)
+}
+
+/// Starts from the snapshot in `options.state_in`, or with no clients
+fn load_processor(options: &Options) -> Result<CsvProcessor, EngineError> {
+    let policy = Arc::clone(&options.dispute_policy);
+    let mut processor = match &options.state_in {
+        Some(path) => CsvProcessor::from_snapshot(load_snapshot(path)?, policy),
+        None => CsvProcessor::with_policy(policy),
+    };
+    processor.record_history(options.history);
+    processor.keep_rejections(options.keep_rejections);
+    Ok(processor)
+}
+
+fn build_report(
+    row_processor: &CsvProcessor,
+    malformed: Vec<RowError>,
+    order: AccountOrder,
+) -> PaymentsReport {
+    PaymentsReport {
+        accounts: row_processor
+            .client_results(order)
+            .into_iter()
+            .map(Account::from)
+            .collect(),
+        rejections: row_processor.rejections().cloned().collect(),
+        flagged_count: row_processor.flagged_count(),
+        malformed,
+        stopped_early: false,
+    }
+}
+
+/// Applies rows on the calling thread, or across worker threads
+enum Runner {
+    Single(CsvProcessor),
+    Sharded(ShardedProcessor),
+}
+
+impl Runner {
+    fn process_row(&mut self, row: TxRow, source: Source) {
+        match self {
+            Runner::Single(processor) => {
+                let _ = processor.process_row_from(&row, source);
+            }
+            Runner::Sharded(processor) => processor.process_row(row, source),
+        }
+    }
+
+    /// Audit events ready to be written, in input order
+    fn take_audit(&mut self) -> Vec<AuditEvent> {
+        match self {
+            Runner::Single(processor) => processor.take_audit(),
+            Runner::Sharded(processor) => processor.take_audit(),
+        }
+    }
 
-    let result = output_csv(&clients)?;
-    Ok(result)
+    fn finish(self) -> CsvProcessor {
+        match self {
+            Runner::Single(processor) => processor,
+            Runner::Sharded(processor) => processor.finish(),
+        }
+    }
 }
@@ -0,0 +1,133 @@
+use std::{io, net::TcpListener};
+
+use serde::Serialize;
+use serde_json::Value;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::client_mod::outcome::Applied;
+use crate::csv_processor_mod::csv_processor::{AccountOrder, CsvProcessor};
+use crate::error_mod::error::EngineError;
+use crate::io_mod::csv_io::{TxRow, TxType};
+
+/// Result of one submitted transaction, with the same reason codes as the rejections report
+#[derive(Serialize)]
+struct TxOutcome {
+    #[serde(rename = "type")]
+    tx_type: TxType,
+    client: u16,
+    tx: u32,
+    /// `ok`, `queued` or `rejected`
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+type JsonResponse = (u16, String);
+
+/// Serves a single engine over HTTP, handling one request at a time:
+///
+/// - `POST /transactions` takes one `TxRow` object, or an array of them applied in order, and
+///   answers with the outcome of each in the same shape
+/// - `GET /clients/{id}` answers with a single account
+/// - `GET /clients` answers with every account, ordered by client id
+pub fn serve_http(listener: TcpListener, mut processor: CsvProcessor) -> Result<(), EngineError> {
+    let server = Server::from_listener(listener, None).map_err(io::Error::other)?;
+    for mut request in server.incoming_requests() {
+        let (status, body) = route(&mut request, &mut processor);
+        let content_type =
+            Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(content_type);
+        // a client which went away only affects its own request
+        let _ = request.respond(response);
+    }
+    Ok(())
+}
+
+fn route(request: &mut Request, processor: &mut CsvProcessor) -> JsonResponse {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Post, ["transactions"]) => {
+            let mut body = String::new();
+            match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => submit(&body, processor),
+                Err(e) => error(400, e.to_string()),
+            }
+        }
+        (Method::Get, ["clients"]) => json(200, &processor.client_results(AccountOrder::ClientId)),
+        (Method::Get, ["clients", id]) => {
+            match id.parse::<u16>().ok().and_then(|id| processor.client(id)) {
+                Some(client) => json(200, client),
+                None => error(404, format!("unknown client '{}'", id)),
+            }
+        }
+        (_, ["transactions"] | ["clients"] | ["clients", _]) => {
+            error(405, "method not allowed".to_string())
+        }
+        _ => error(404, format!("no route for '{}'", path)),
+    }
+}
+
+/// Applies a single row or an array of rows, answering in the same shape
+fn submit(body: &str, processor: &mut CsvProcessor) -> JsonResponse {
+    let value: Value = match serde_json::from_str(body) {
+        Ok(value) => value,
+        Err(e) => return error(400, e.to_string()),
+    };
+
+    if value.is_array() {
+        match serde_json::from_value::<Vec<TxRow>>(value) {
+            Ok(rows) => {
+                let outcomes: Vec<TxOutcome> =
+                    rows.iter().map(|row| apply(row, processor)).collect();
+                json(200, &outcomes)
+            }
+            Err(e) => error(400, e.to_string()),
+        }
+    } else {
+        match serde_json::from_value::<TxRow>(value) {
+            Ok(row) => json(200, &apply(&row, processor)),
+            Err(e) => error(400, e.to_string()),
+        }
+    }
+}
+
+fn apply(row: &TxRow, processor: &mut CsvProcessor) -> TxOutcome {
+    let (status, reason) = match processor.process_row(row) {
+        Ok(Applied::Completed) => ("ok", None),
+        Ok(Applied::Queued) => ("queued", None),
+        Err(e) => ("rejected", Some(e.code())),
+    };
+    TxOutcome {
+        tx_type: row.tx_type,
+        client: row.client,
+        tx: row.tx,
+        status,
+        reason,
+    }
+}
+
+fn json<T: Serialize + ?Sized>(status: u16, body: &T) -> JsonResponse {
+    match serde_json::to_string(body) {
+        Ok(body) => (status, body),
+        Err(e) => error(500, e.to_string()),
+    }
+}
+
+fn error(status: u16, error: String) -> JsonResponse {
+    let body = serde_json::to_string(&ErrorBody { error }).unwrap_or_default();
+    (status, body)
+}
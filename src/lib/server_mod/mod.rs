@@ -0,0 +1,3 @@
+#[cfg(feature = "http")]
+pub mod http;
+pub mod server;
@@ -0,0 +1,102 @@
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::client_mod::outcome::Applied;
+use crate::csv_processor_mod::csv_processor::CsvProcessor;
+use crate::error_mod::error::EngineError;
+use crate::io_mod::csv_io::{output_client_csv, parse_csv_line, InputError};
+
+/// Pause after a connection could not be accepted, so that a lasting failure such as running
+/// out of file descriptors does not spin
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Serves a single engine to any number of connections, one thread per connection. Connections
+/// which cannot be accepted are reported on stderr and skipped.
+///
+/// Each line sent is either a transaction row in the csv input format, without a header, or
+/// `query <client>`. Every line gets exactly one line back:
+///
+/// - `ok` or `queued` when a row was applied, or held until funds are available
+/// - `rejected <reason>` when a row had no effect, with the same reason code as the
+///   rejections report
+/// - the client's accounts csv row (`client,available,held,total,locked`) for a query
+/// - `error <message>` for rows which could not be read and unknown clients
+pub fn serve(listener: TcpListener, processor: CsvProcessor) -> io::Result<()> {
+    let processor = Arc::new(Mutex::new(processor));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("could not accept connection: {}", e);
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let processor = Arc::clone(&processor);
+        thread::spawn(move || {
+            // a failed connection only affects its own client
+            let _ = handle_connection(stream, &processor);
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, processor: &Mutex<CsvProcessor>) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = respond(line.trim(), i as u64 + 1, processor);
+        writeln!(writer, "{}", response)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn respond(line: &str, line_no: u64, processor: &Mutex<CsvProcessor>) -> String {
+    let mut processor = processor.lock().expect("engine lock poisoned");
+
+    if let Some(client) = line.strip_prefix("query") {
+        let client = client.trim();
+        return match client
+            .parse::<u16>()
+            .ok()
+            .and_then(|id| processor.client(id))
+        {
+            Some(c) => client_line(output_client_csv(c)),
+            None => format!("error unknown client '{}'", client),
+        };
+    }
+
+    match parse_csv_line(line) {
+        Ok(row) => match processor.process_row(&row) {
+            Ok(Applied::Completed) => "ok".to_string(),
+            Ok(Applied::Queued) => "queued".to_string(),
+            Err(e) => format!("rejected {}", e.code()),
+        },
+        Err(
+            InputError::Syntax { error: mut e, .. }
+            | InputError::Schema(mut e)
+            | InputError::Amount(mut e),
+        ) => {
+            e.line = line_no;
+            format!("error {}", e)
+        }
+        Err(e) => format!("error {}", EngineError::from(e)),
+    }
+}
+
+fn client_line(csv: Result<String, EngineError>) -> String {
+    match csv {
+        Ok(row) => row.trim_end().to_string(),
+        Err(e) => format!("error {}", e),
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::client_mod::client::ClientState;
+
+/// Bumped whenever the snapshot layout changes in a way older readers cannot load. Version 2
+/// added client histories, and version 1 snapshots are still loaded with empty ones.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Complete processor state, saved between runs so that each batch only needs to contain new
+/// transactions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub clients: Vec<ClientState>,
+    /// Client which made each transaction id seen so far
+    pub tx_owners: BTreeMap<u32, u16>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "could not access snapshot: {}", e),
+            SnapshotError::Format(e) => write!(f, "invalid snapshot: {}", e),
+            SnapshotError::UnsupportedVersion(v) => write!(
+                f,
+                "snapshot version {} is not supported, expected {}",
+                v, SNAPSHOT_VERSION
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Format(e)
+    }
+}
+
+pub fn load_snapshot(path: &Path) -> Result<Snapshot, SnapshotError> {
+    let file = File::open(path)?;
+    let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file))?;
+    if !(1..=SNAPSHOT_VERSION).contains(&snapshot.version) {
+        return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+    }
+    Ok(snapshot)
+}
+
+/// Writes to a temporary file first, so a crash mid-write never leaves a truncated snapshot in
+/// place of the previous one
+pub fn save_snapshot(path: &Path, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+    let tmp_path = path.with_extension("tmp");
+    let mut wtr = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut wtr, snapshot)?;
+    wtr.flush()?;
+    wtr.get_ref().sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
@@ -1,25 +1,615 @@
-use std::{env, process};
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    process,
+    sync::Arc,
+};
 
-use toy_payments_lib::process_payments;
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use toy_payments_lib::{
+    open_input, process_payments_inputs, replay_audit, serve_payments, validate_inputs,
+    AccountOrder, CompressedWriter, Compression, DepositsOnly, DisputePolicy, Engine, EngineError,
+    InputFormat, Options, ParseMode, PaymentsReport, ReportFormat, StopAt, WithdrawalReversal,
+};
+
+/// Address `serve` listens on unless `--addr` is given
+const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+/// Exit status of `validate` when some rows are malformed
+const INVALID_ROWS: i32 = 9;
+
+/// Exit status of `replay` when the rebuilt accounts differ from the expected report
+const REPLAY_MISMATCH: i32 = 11;
+
+/// Applies deposits, withdrawals, disputes, resolves and chargebacks to client accounts
+#[derive(Parser)]
+#[command(name = "toy_payments_bin", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process transactions and print the resulting accounts (the default command)
+    Process(Box<ProcessArgs>),
+    /// Check that every row can be read, without applying any transaction
+    Validate {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Print one client's statement: every transaction it sent and the balances it resulted in
+    Inspect {
+        /// Client to show
+        #[arg(long)]
+        client: u16,
+        /// Format of the statement
+        #[arg(long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        engine: EngineArgs,
+        #[command(flatten)]
+        parse: ParseArgs,
+    },
+    /// Rebuild the accounts from an audit log written with `process --audit` and print them
+    Replay {
+        /// Audit log to replay
+        log: PathBuf,
+        /// Check that the rebuilt accounts match this accounts report exactly, instead of
+        /// printing them
+        #[arg(long)]
+        expect: Option<PathBuf>,
+        /// Dispute policy the log was written with
+        #[arg(long, value_enum, default_value_t = PolicyArg::Deposits)]
+        dispute_policy: PolicyArg,
+    },
+    /// Apply transactions sent over TCP and answer balance queries
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = DEFAULT_ADDR)]
+        addr: String,
+        /// Serve a JSON API over HTTP instead of csv lines
+        #[cfg(feature = "http")]
+        #[arg(long)]
+        http: bool,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Write the accounts report to this file instead of stdout
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Format of the accounts report
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    /// Add each account's open dispute count and pending withdrawals to the report
+    #[arg(long)]
+    details: bool,
+    /// Report the accounts as they were just before this data row, counting the rows of every
+    /// input from 1
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["stop_at_tx", "state_out", "journal", "audit"]
+    )]
+    stop_at_row: Option<u64>,
+    /// Report the accounts as they were just before the first row with this transaction id
+    #[arg(long, conflicts_with_all = ["state_out", "journal", "audit"])]
+    stop_at_tx: Option<u32>,
+    /// Order of the accounts report
+    #[arg(long, value_enum, default_value_t = SortArg::Client)]
+    sort: SortArg,
+    /// Compress the accounts report, by default taken from the `--output` extension
+    #[arg(long, value_enum)]
+    compress: Option<CompressArg>,
+    /// Save the account state to this snapshot once all rows have been processed
+    #[arg(long)]
+    state_out: Option<PathBuf>,
+    /// Keep every client's transaction history in the `--state-out` snapshot, for `inspect`
+    #[arg(long)]
+    history: bool,
+    /// Write-ahead journal making the run crash-safe
+    #[arg(long, requires = "state_out")]
+    journal: Option<PathBuf>,
+    /// Write an event for every change to an account's balances to this JSON Lines file
+    #[arg(long)]
+    audit: Option<PathBuf>,
+    /// Worker threads to spread clients across
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    threads: u16,
+    /// Write rejected rows and their reason to this csv
+    #[arg(long)]
+    rejections: Option<PathBuf>,
+    /// Write rejections flagged as possible fraud to this csv
+    #[arg(long)]
+    flagged: Option<PathBuf>,
+    /// Write rows skipped in lenient mode to this csv instead of stderr
+    #[arg(long)]
+    errors: Option<PathBuf>,
+    #[command(flatten)]
+    engine: EngineArgs,
+    #[command(flatten)]
+    parse: ParseArgs,
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// Inputs processed in order as one stream, `-` or none for stdin
+    inputs: Vec<PathBuf>,
+    /// Format of the inputs, by default taken from their extension or csv
+    #[arg(long, value_enum)]
+    input_format: Option<InputFormatArg>,
+}
+
+/// Settings shared by every command which applies transactions
+#[derive(Args)]
+struct EngineArgs {
+    /// Which transactions can be disputed
+    #[arg(long, value_enum, default_value_t = PolicyArg::Deposits)]
+    dispute_policy: PolicyArg,
+    /// Resume from a snapshot saved by an earlier run
+    #[arg(long)]
+    state_in: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ParseArgs {
+    /// How malformed rows are handled
+    #[arg(long, value_enum, default_value_t = ModeArg::Strict)]
+    mode: ModeArg,
+    /// Stop at the first malformed row, same as `--mode strict`
+    #[arg(long, conflicts_with_all = ["mode", "lenient"])]
+    strict: bool,
+    /// Skip and report malformed rows, same as `--mode lenient`
+    #[arg(long, conflicts_with = "mode")]
+    lenient: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    /// A JSON array of accounts
+    Json,
+    /// One JSON account per line
+    Jsonl,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputFormatArg {
+    Csv,
+    Jsonl,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortArg {
+    /// Ascending client id
+    Client,
+    /// Largest total first
+    Total,
+    /// Order in which accounts were opened
+    Insertion,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CompressArg {
+    None,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PolicyArg {
+    Deposits,
+    Withdrawals,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ModeArg {
+    Strict,
+    Lenient,
+}
+
+impl PolicyArg {
+    fn policy(self) -> Arc<dyn DisputePolicy> {
+        match self {
+            PolicyArg::Deposits => Arc::new(DepositsOnly),
+            PolicyArg::Withdrawals => Arc::new(WithdrawalReversal),
+        }
+    }
+}
+
+impl EngineArgs {
+    fn options(&self) -> Options {
+        Options {
+            dispute_policy: self.dispute_policy.policy(),
+            state_in: self.state_in.clone(),
+            ..Options::default()
+        }
+    }
+}
+
+impl ProcessArgs {
+    /// Compression of the accounts report, from `--compress` or else the `--output` extension
+    fn compression(&self) -> Compression {
+        match (self.compress, &self.output) {
+            (Some(arg), _) => arg.into(),
+            (None, Some(path)) => Compression::from_path(path),
+            (None, None) => Compression::None,
+        }
+    }
+
+    fn stop_at(&self) -> Option<StopAt> {
+        match (self.stop_at_row, self.stop_at_tx) {
+            (Some(row), _) => Some(StopAt::Row(row)),
+            (None, Some(tx)) => Some(StopAt::Tx(tx)),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Command {
+    fn input(&self) -> Option<&InputArgs> {
+        match self {
+            Command::Process(args) => Some(&args.input),
+            Command::Validate { input } | Command::Inspect { input, .. } => Some(input),
+            _ => None,
+        }
+    }
+}
+
+impl InputArgs {
+    /// Format given by `--input-format`, or else the one every input's extension agrees on.
+    /// Inputs which disagree are rejected by [`InputArgs::check_format`].
+    fn format(&self) -> InputFormat {
+        match self.input_format {
+            Some(InputFormatArg::Csv) => InputFormat::Csv,
+            Some(InputFormatArg::Jsonl) => InputFormat::Jsonl,
+            None => self.extension_formats().next().unwrap_or_default(),
+        }
+    }
+
+    /// Fails when the inputs' extensions mix formats and `--input-format` does not pick one
+    fn check_format(&self) -> Result<(), clap::Error> {
+        let mut formats = self.extension_formats();
+        let first = formats.next();
+        if self.input_format.is_none() && formats.any(|format| Some(format) != first) {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "inputs mix csv and jsonl, pick one with --input-format",
+            ));
+        }
+        Ok(())
+    }
+
+    fn extension_formats(&self) -> impl Iterator<Item = InputFormat> + '_ {
+        self.inputs.iter().filter_map(|p| InputFormat::from_path(p))
+    }
+}
+
+impl ParseArgs {
+    fn mode(&self) -> ParseMode {
+        if self.lenient || self.mode == ModeArg::Lenient {
+            ParseMode::Lenient
+        } else {
+            ParseMode::Strict
+        }
+    }
+}
+
+impl From<Format> for ReportFormat {
+    fn from(arg: Format) -> Self {
+        match arg {
+            Format::Csv => ReportFormat::Csv,
+            Format::Json => ReportFormat::Json,
+            Format::Jsonl => ReportFormat::Jsonl,
+        }
+    }
+}
+
+impl From<SortArg> for AccountOrder {
+    fn from(arg: SortArg) -> Self {
+        match arg {
+            SortArg::Client => AccountOrder::ClientId,
+            SortArg::Total => AccountOrder::Total,
+            SortArg::Insertion => AccountOrder::Insertion,
+        }
+    }
+}
+
+impl From<CompressArg> for Compression {
+    fn from(arg: CompressArg) -> Self {
+        match arg {
+            CompressArg::None => Compression::None,
+            CompressArg::Gzip => Compression::Gzip,
+            CompressArg::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+/// Parses the command line, running `process` when no command is given. Invalid arguments exit
+/// with 1, `--help` and `--version` with 0.
+fn parse_cli() -> Cli {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let has_command = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+        matches!(
+            arg,
+            "process"
+                | "validate"
+                | "inspect"
+                | "replay"
+                | "serve"
+                | "help"
+                | "-h"
+                | "--help"
+                | "-V"
+                | "--version"
+        )
+    });
+    if !has_command {
+        args.insert(1, OsString::from("process"));
+    }
+
+    let cli = Cli::try_parse_from(args).and_then(|cli| {
+        if let Some(input) = cli.command.input() {
+            input.check_format()?;
+        }
+        Ok(cli)
+    });
+    cli.unwrap_or_else(|e| {
+        let _ = e.print();
+        process::exit(if e.use_stderr() { 1 } else { 0 });
+    })
+}
+
+/// Opens every input up front, so that a missing file is reported before any row is applied
+fn open_inputs(paths: &[PathBuf]) -> Result<Vec<Box<dyn Read>>, EngineError> {
+    if paths.is_empty() {
+        return Ok(vec![open_input("-".as_ref())?]);
+    }
+    paths
+        .iter()
+        .map(|path| Ok(open_input(path.as_os_str())?))
+        .collect()
+}
+
+/// Writes the optional side reports, listing skipped rows on stderr if no error file was given
+fn write_reports(args: &ProcessArgs, report: &PaymentsReport) -> Result<(), EngineError> {
+    if let Some(path) = &args.rejections {
+        fs::write(path, report.rejections_csv()?)?;
+    }
+
+    let flagged = report.flagged_count;
+    if flagged > 0 {
+        eprintln!(
+            "warning: {} cross-client transaction references flagged",
+            flagged
+        );
+    }
+    if let Some(path) = &args.flagged {
+        fs::write(path, report.flagged_csv()?)?;
+    }
+
+    if !report.malformed.is_empty() {
+        eprintln!("skipped {} malformed rows", report.malformed.len());
+        match &args.errors {
+            Some(path) => fs::write(path, report.malformed_csv()?)?,
+            None => report.malformed.iter().for_each(|e| eprintln!("{}", e)),
+        }
+    }
+    Ok(())
+}
+
+/// Streams the accounts report to `--output` or stdout, compressed if asked to
+fn write_accounts(args: &ProcessArgs, report: &PaymentsReport) -> Result<(), EngineError> {
+    let compression = args.compression();
+    match &args.output {
+        Some(path) => write_report(
+            BufWriter::new(File::create(path)?),
+            compression,
+            args,
+            report,
+        ),
+        None => write_report(io::stdout().lock(), compression, args, report),
+    }
+}
+
+fn write_report<W: Write>(
+    out: W,
+    compression: Compression,
+    args: &ProcessArgs,
+    report: &PaymentsReport,
+) -> Result<(), EngineError> {
+    let mut writer = CompressedWriter::new(out, compression)?;
+    // a point-in-time report is about what was still in flight
+    let details = args.details || args.stop_at().is_some();
+    report.write_accounts(&mut writer, args.format.into(), details)?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn process(args: ProcessArgs) -> Result<i32, EngineError> {
+    // checked before any row is applied or state saved, as the report could not be written
+    args.compression().ensure_supported()?;
+    let options = Options {
+        mode: args.parse.mode(),
+        input_format: args.input.format(),
+        state_out: args.state_out.clone(),
+        journal: args.journal.clone(),
+        threads: args.threads.into(),
+        order: args.sort.into(),
+        keep_rejections: args.rejections.is_some() || args.flagged.is_some(),
+        history: args.history,
+        audit: args.audit.clone(),
+        stop_at: args.stop_at(),
+        ..args.engine.options()
+    };
+
+    let report = process_payments_inputs(open_inputs(&args.input.inputs)?, options)?;
+    match args.stop_at() {
+        Some(StopAt::Row(row)) if report.stopped_early => eprintln!("stopped before row {}", row),
+        Some(StopAt::Tx(tx)) if report.stopped_early => eprintln!("stopped before tx {}", tx),
+        Some(_) => eprintln!("warning: stop point not reached, every row was processed"),
+        None => {}
+    }
+    write_reports(&args, &report)?;
+    write_accounts(&args, &report)?;
+    Ok(0)
+}
+
+fn validate(input: &InputArgs) -> Result<i32, EngineError> {
+    let validation = validate_inputs(open_inputs(&input.inputs)?, input.format())?;
+    validation.malformed.iter().for_each(|e| eprintln!("{}", e));
+    eprintln!(
+        "{} rows read, {} malformed",
+        validation.rows,
+        validation.malformed.len()
+    );
+    Ok(if validation.malformed.is_empty() {
+        0
+    } else {
+        INVALID_ROWS
+    })
+}
+
+fn inspect(
+    client: u16,
+    format: Format,
+    input: &InputArgs,
+    engine_args: &EngineArgs,
+    parse: &ParseArgs,
+) -> Result<i32, EngineError> {
+    let options = Options {
+        history: true,
+        ..engine_args.options()
+    };
+    let mut engine = Engine::from_options(&options)?;
+    // a snapshot can be inspected on its own, so stdin is then only read when named with `-`
+    let readers = match (&input.inputs[..], &engine_args.state_in) {
+        ([], Some(_)) => Vec::new(),
+        (paths, _) => open_inputs(paths)?,
+    };
+    let input_format = input.format();
+    for reader in readers {
+        let malformed = engine.process_input(reader, input_format, parse.mode())?;
+        malformed
+            .iter()
+            .for_each(|e| eprintln!("skipped malformed row: {}", e));
+    }
+
+    if engine.account(client).is_none() {
+        eprintln!("error: unknown client {}", client);
+        return Ok(1);
+    }
+    engine.write_statement(client, io::stdout().lock(), format.into())?;
+    Ok(0)
+}
+
+fn replay(
+    log: &PathBuf,
+    expect: Option<&PathBuf>,
+    dispute_policy: PolicyArg,
+) -> Result<i32, EngineError> {
+    let replayed = PaymentsReport {
+        accounts: replay_audit(File::open(log)?, &*dispute_policy.policy())?,
+        rejections: Vec::new(),
+        flagged_count: 0,
+        malformed: Vec::new(),
+        stopped_early: false,
+    };
+    let report = replayed.accounts_csv()?;
+    let Some(expected_path) = expect else {
+        io::stdout().lock().write_all(report.as_bytes())?;
+        return Ok(0);
+    };
+
+    let expected = fs::read_to_string(expected_path)?;
+    if report == expected {
+        eprintln!(
+            "{} accounts match {}",
+            replayed.accounts.len(),
+            expected_path.display()
+        );
+        return Ok(0);
+    }
+    // a shorter report differs on the line after its last one
+    let line = report
+        .lines()
+        .zip(expected.lines())
+        .position(|(got, want)| got != want)
+        .unwrap_or_else(|| report.lines().count().min(expected.lines().count()));
+    eprintln!(
+        "error: replayed accounts differ from {} at line {}",
+        expected_path.display(),
+        line + 1
+    );
+    Ok(REPLAY_MISMATCH)
+}
+
+fn serve(addr: &str, http: bool, options: &Options) -> Result<i32, EngineError> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("listening on {}", addr);
+    match http {
+        #[cfg(feature = "http")]
+        true => toy_payments_lib::serve_payments_http(listener, options)?,
+        _ => serve_payments(listener, options)?,
+    }
+    Ok(0)
+}
+
+/// Exit status for each kind of failure, so that callers can tell them apart. Invalid
+/// arguments exit with 1.
+fn exit_code(e: &EngineError) -> i32 {
+    match e {
+        EngineError::Options(_) => 1,
+        EngineError::Io(_) => 2,
+        EngineError::Syntax { .. } => 3,
+        EngineError::Schema(_) => 4,
+        EngineError::Amount(_) => 5,
+        EngineError::Output(_) => 6,
+        EngineError::Snapshot(_) => 7,
+        EngineError::Journal(_) => 8,
+        EngineError::Audit(_) => 10,
+    }
+}
 
 fn main() {
-    match env::args_os().nth(1) {
-        None => {
-            eprintln!("Missing csv file argument");
-            process::exit(1);
-        }
-        Some(csv_path) => {
-            match process_payments(&csv_path) {
-                Ok(result) => {
-                    println!("{}", result);
-                    process::exit(0);
-                }
-                Err(e) => {
-                    // error occurred
-                    eprintln!("an error occurred: {:#?}", e);
-                    process::exit(1);
-                }
-            }
+    let result = match parse_cli().command {
+        Command::Process(args) => process(*args),
+        Command::Validate { input } => validate(&input),
+        Command::Inspect {
+            client,
+            format,
+            input,
+            engine,
+            parse,
+        } => inspect(client, format, &input, &engine, &parse),
+        Command::Replay {
+            log,
+            expect,
+            dispute_policy,
+        } => replay(&log, expect.as_ref(), dispute_policy),
+        #[cfg(feature = "http")]
+        Command::Serve { addr, http, engine } => serve(&addr, http, &engine.options()),
+        #[cfg(not(feature = "http"))]
+        Command::Serve { addr, engine } => serve(&addr, false, &engine.options()),
+    };
+
+    match result {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(exit_code(&e));
         }
     }
 }
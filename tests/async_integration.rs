@@ -0,0 +1,81 @@
+#![cfg(feature = "async")]
+
+use std::{pin::Pin, time::Duration};
+
+use futures_util::stream::{self, Stream, StreamExt};
+use test_utils::create_csv;
+use toy_payments_lib::{process_streams, Amount, Options, TxRow, TxType, STREAM_BUFFER};
+
+extern crate test_utils;
+
+type RowStream = Pin<Box<dyn Stream<Item = TxRow> + Send>>;
+
+fn row(tx_type: TxType, client: u16, tx: u32, amount: Option<&str>) -> TxRow {
+    TxRow {
+        tx_type,
+        client,
+        tx,
+        amount: amount.map(|a| a.parse::<Amount>().unwrap()),
+    }
+}
+
+/// Deposits and immediately withdraws the same amount, so any reordering leaves a rejection
+fn deposit_withdraw_pairs(client: u16, pairs: u32) -> Vec<TxRow> {
+    (0..pairs)
+        .flat_map(|i| {
+            let tx = client as u32 * 100_000 + i * 2;
+            [
+                row(TxType::Deposit, client, tx, Some("10.0")),
+                row(TxType::Withdrawal, client, tx + 1, Some("10.0")),
+            ]
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn keeps_order_within_each_stream() {
+    let pairs = STREAM_BUFFER as u32;
+    let streams: Vec<_> = (1..=8)
+        .map(|client| stream::iter(deposit_withdraw_pairs(client, pairs)))
+        .collect();
+
+    let options = Options {
+        keep_rejections: true,
+        ..Options::default()
+    };
+    let report = process_streams(streams, options).await.unwrap();
+
+    let expected = create_csv(
+        ["1", "2", "3", "4", "5", "6", "7", "8"]
+            .into_iter()
+            .map(|id| [id, "0.0000", "0.0000", "0.0000", "false"])
+            .collect(),
+    );
+    assert_eq!(report.accounts_csv().unwrap(), expected);
+    assert!(report.rejections.is_empty());
+}
+
+#[tokio::test]
+async fn merges_streams_arriving_at_different_rates() {
+    let slow = stream::iter(vec![
+        row(TxType::Deposit, 1, 1, Some("5.0")),
+        row(TxType::Dispute, 1, 1, None),
+    ])
+    .then(|row| async move {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        row
+    });
+    let fast = stream::iter(vec![
+        row(TxType::Deposit, 2, 2, Some("3.0")),
+        row(TxType::Withdrawal, 2, 3, Some("1.0")),
+    ]);
+
+    let streams: Vec<RowStream> = vec![Box::pin(slow), Box::pin(fast)];
+    let report = process_streams(streams, Options::default()).await.unwrap();
+
+    let expected = create_csv(vec![
+        ["1", "0.0000", "5.0000", "5.0000", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+    ]);
+    assert_eq!(report.accounts_csv().unwrap(), expected);
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "http")]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+
+use toy_payments_lib::{serve_payments_http, Options};
+
+/// Starts a server on a free localhost port
+fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || serve_payments_http(listener, &Options::default()).unwrap());
+    addr
+}
+
+/// Sends a single request, returning the status code and body
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[test]
+fn submits_transactions_and_reports_outcomes() {
+    let addr = start_server();
+
+    let single = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "10.0"}"#;
+    assert_eq!(
+        request(addr, "POST", "/transactions", single),
+        (
+            200,
+            r#"{"type":"deposit","client":1,"tx":1,"status":"ok"}"#.to_string()
+        )
+    );
+
+    let many = r#"[
+        {"type": "withdrawal", "client": 1, "tx": 2, "amount": "2.5"},
+        {"type": "withdrawal", "client": 1, "tx": 3, "amount": "100.0"},
+        {"type": "deposit", "client": 2, "tx": 1, "amount": "1.0"},
+        {"type": "dispute", "client": 1, "tx": 1}
+    ]"#;
+    assert_eq!(
+        request(addr, "POST", "/transactions", many),
+        (
+            200,
+            concat!(
+                r#"[{"type":"withdrawal","client":1,"tx":2,"status":"ok"},"#,
+                r#"{"type":"withdrawal","client":1,"tx":3,"status":"rejected","reason":"insufficient_funds"},"#,
+                r#"{"type":"deposit","client":2,"tx":1,"status":"rejected","reason":"duplicate_tx"},"#,
+                r#"{"type":"dispute","client":1,"tx":1,"status":"ok"}]"#
+            )
+            .to_string()
+        )
+    );
+
+    let (status, _) = request(addr, "POST", "/transactions", r#"{"type": "deposit"}"#);
+    assert_eq!(status, 400);
+}
+
+#[test]
+fn queries_accounts() {
+    let addr = start_server();
+    let rows = r#"[
+        {"type": "deposit", "client": 2, "tx": 1, "amount": "1.5"},
+        {"type": "deposit", "client": 1, "tx": 2, "amount": "3"}
+    ]"#;
+    request(addr, "POST", "/transactions", rows);
+
+    assert_eq!(
+        request(addr, "GET", "/clients/2", ""),
+        (
+            200,
+            r#"{"client":2,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+                .to_string()
+        )
+    );
+    assert_eq!(
+        request(addr, "GET", "/clients", ""),
+        (
+            200,
+            concat!(
+                r#"[{"client":1,"available":"3.0000","held":"0.0000","total":"3.0000","locked":false},"#,
+                r#"{"client":2,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}]"#
+            )
+            .to_string()
+        )
+    );
+    assert_eq!(request(addr, "GET", "/clients/7", "").0, 404);
+    assert_eq!(request(addr, "GET", "/accounts", "").0, 404);
+    assert_eq!(request(addr, "DELETE", "/clients", "").0, 405);
+}
@@ -1,7 +1,25 @@
-use std::ffi::OsString;
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
 
-use test_utils::{assert_unsorted_eq, create_csv};
-use toy_payments_lib::process_payments;
+use test_utils::create_csv;
+use toy_payments_lib::{
+    process_payments, process_payments_from_reader, process_payments_inputs,
+    process_payments_report, replay_audit, serve_payments, validate_inputs, AccountOrder,
+    DepositsOnly, DisputePolicy, Engine, EngineError, InputFormat, Options, ParseMode,
+    PaymentsReport, Rejection, ReportFormat, RowError, StopAt, TxError, TxType, WithdrawalReversal,
+    SNAPSHOT_VERSION,
+};
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use toy_payments_lib::{write_compressed, Compression};
 
 extern crate test_utils;
 
@@ -12,7 +30,7 @@ fn basic_example() {
         ["1", "1.5000", "0.0000", "1.5000", "false"],
         ["2", "2.0000", "0.0000", "2.0000", "false"],
     ]);
-    assert_unsorted_eq(&sut, &expected);
+    assert_eq!(sut, expected);
 }
 
 #[test]
@@ -86,3 +104,902 @@ fn chargeback_will_block_account_and_reduce_funds() {
     let expected = create_csv(vec![["1", "-50.0000", "0.0000", "-50.0000", "true"]]);
     assert_eq!(sut, expected)
 }
+
+#[test]
+fn repeated_fractional_amounts_are_exact() {
+    let sut = process_payments(&OsString::from("tests/resources/precise_amounts.csv")).unwrap();
+    let expected = create_csv(vec![["1", "0.1001", "0.0000", "0.1001", "false"]]);
+    assert_eq!(sut, expected)
+}
+
+#[test]
+fn amounts_with_more_than_four_decimals_are_rejected() {
+    let sut = process_payments(&OsString::from("tests/resources/too_many_decimals.csv"));
+    assert!(matches!(sut, Err(EngineError::Amount(_))))
+}
+
+#[test]
+fn negative_amounts_are_rejected() {
+    let file = File::open("tests/resources/negative_amounts.csv").unwrap();
+    let options = Options {
+        mode: ParseMode::Lenient,
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap();
+    let expected = create_csv(vec![["1", "10.0000", "0.0000", "10.0000", "false"]]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+    let lines: Vec<u64> = sut.malformed.iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![3, 4]);
+}
+
+/// Generates deposit rows on demand so the input is never held in memory as a whole
+struct GeneratedDeposits {
+    next_tx: u32,
+    rows: u32,
+    pending: Vec<u8>,
+}
+
+impl Read for GeneratedDeposits {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            if self.next_tx == 0 {
+                self.pending = b"type,client,tx,amount\n".to_vec();
+            } else if self.next_tx <= self.rows {
+                self.pending =
+                    format!("deposit,{},{},1.0\n", self.next_tx % 3, self.next_tx).into_bytes();
+            } else {
+                return Ok(0);
+            }
+            self.next_tx += 1;
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+#[test]
+fn rows_are_streamed_from_any_reader() {
+    let reader = GeneratedDeposits {
+        next_tx: 0,
+        rows: 30_000,
+        pending: Vec::new(),
+    };
+    let sut = process_payments_from_reader(reader).unwrap();
+    let expected = create_csv(vec![
+        ["0", "10000.0000", "0.0000", "10000.0000", "false"],
+        ["1", "10000.0000", "0.0000", "10000.0000", "false"],
+        ["2", "10000.0000", "0.0000", "10000.0000", "false"],
+    ]);
+    assert_eq!(sut, expected);
+}
+
+fn keeping_rejections() -> Options {
+    Options {
+        keep_rejections: true,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn rejected_rows_are_reported_with_reason() {
+    let file = File::open("tests/resources/false_chargebacks.csv").unwrap();
+    let sut = process_payments_report(file, keeping_rejections()).unwrap();
+    let rejection = |tx_type, client, tx, error| Rejection {
+        tx_type,
+        client,
+        tx,
+        error,
+    };
+    let expected = vec![
+        rejection(TxType::ChargeBack, 1, 1, TxError::NotDisputed),
+        rejection(TxType::ChargeBack, 1, 2, TxError::NotDisputed),
+        rejection(TxType::ChargeBack, 2, 1, TxError::CrossClientTx),
+    ];
+    assert_eq!(sut.rejections, expected)
+}
+
+#[test]
+fn rejections_are_only_kept_when_asked_for() {
+    let file = File::open("tests/resources/cross_client_disputes.csv").unwrap();
+    let sut = process_payments_report(file, Options::default()).unwrap();
+    assert!(sut.rejections.is_empty());
+    assert_eq!(sut.flagged_count, 2);
+}
+
+#[test]
+fn false_disputes_are_reported_with_reason() {
+    let file = File::open("tests/resources/false_disputes.csv").unwrap();
+    let sut = process_payments_report(file, keeping_rejections()).unwrap();
+    let errors: Vec<TxError> = sut.rejections.iter().map(|r| r.error).collect();
+    assert_eq!(errors, vec![TxError::NotDisputable, TxError::UnknownTx])
+}
+
+#[test]
+fn rejections_are_written_as_csv() {
+    let file = File::open("tests/resources/false_chargebacks.csv").unwrap();
+    let sut = process_payments_report(file, keeping_rejections())
+        .unwrap()
+        .rejections_csv()
+        .unwrap();
+    let expected = "type,client,tx,reason\n\
+                    chargeback,1,1,not_disputed\n\
+                    chargeback,1,2,not_disputed\n\
+                    chargeback,2,1,cross_client_tx\n";
+    assert_eq!(sut, expected)
+}
+
+#[test]
+fn strict_mode_aborts_on_first_malformed_row() {
+    let file = File::open("tests/resources/malformed_rows.csv").unwrap();
+    let sut = process_payments_report(file, Options::default()).unwrap_err();
+    assert!(matches!(sut, EngineError::Schema(_)));
+    assert_eq!(sut.to_string(), "input 1, line 3, column 4: missing amount")
+}
+
+#[test]
+fn csv_syntax_errors_report_line_and_byte() {
+    let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0,extra\n";
+    let sut = process_payments_report(input.as_bytes(), Options::default()).unwrap_err();
+    match sut {
+        EngineError::Syntax { error, byte } => assert_eq!((error.line, byte), (3, 38)),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[test]
+fn missing_input_file_is_an_io_error() {
+    let sut = process_payments(&OsString::from("tests/resources/does_not_exist.csv"));
+    assert!(matches!(sut, Err(EngineError::Io(_))))
+}
+
+#[test]
+fn lenient_mode_skips_and_reports_malformed_rows() {
+    let file = File::open("tests/resources/malformed_rows.csv").unwrap();
+    let options = Options {
+        mode: ParseMode::Lenient,
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap();
+    let expected = create_csv(vec![["1", "7.5000", "0.0000", "7.5000", "false"]]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+
+    let error = |line, column, message: &str| RowError {
+        input: 1,
+        line,
+        column,
+        message: message.to_string(),
+    };
+    let expected_malformed = vec![
+        error(3, Some(4), "missing amount"),
+        error(
+            4,
+            Some(4),
+            "amount '1.00001' has more than 4 decimal places",
+        ),
+        error(5, Some(1), "unknown transaction type 'refund'"),
+        error(6, Some(2), "invalid client id 'x'"),
+        error(7, None, "expected 4 fields, found 3"),
+    ];
+    assert_eq!(sut.malformed, expected_malformed);
+    let errors_csv = sut.malformed_csv().unwrap();
+    assert!(errors_csv.starts_with("input,line,column,error\n1,3,4,missing amount\n"));
+}
+
+#[test]
+fn validation_reads_every_row_without_applying_any() {
+    let malformed = File::open("tests/resources/malformed_rows.csv").unwrap();
+    let basic = File::open("tests/resources/basic_example.csv").unwrap();
+    let sut = validate_inputs([basic, malformed], InputFormat::Csv).unwrap();
+    assert_eq!(sut.rows, 7);
+    let lines: Vec<(usize, u64)> = sut.malformed.iter().map(|e| (e.input, e.line)).collect();
+    assert_eq!(lines, vec![(2, 3), (2, 4), (2, 5), (2, 6), (2, 7)]);
+    assert_eq!(
+        sut.malformed[0].to_string(),
+        "input 2, line 3, column 4: missing amount"
+    );
+}
+
+#[test]
+fn accounts_are_reported_in_the_requested_order() {
+    let input = "type,client,tx,amount\n\
+                 deposit,3,1,1.0\n\
+                 deposit,1,2,5.0\n\
+                 deposit,2,3,6.0\n\
+                 withdrawal,3,4,2.0\n";
+    let clients = |order| {
+        let options = Options {
+            order,
+            ..Options::default()
+        };
+        let report = process_payments_report(input.as_bytes(), options).unwrap();
+        report.accounts.iter().map(|a| a.client).collect::<Vec<_>>()
+    };
+    assert_eq!(clients(AccountOrder::ClientId), vec![1, 2, 3]);
+    assert_eq!(clients(AccountOrder::Total), vec![2, 1, 3]);
+    assert_eq!(clients(AccountOrder::Insertion), vec![3, 1, 2]);
+
+    // opening order carries over a snapshot, ahead of accounts opened by the next run
+    let state = temp_path("order_state.json");
+    let options = Options {
+        state_out: Some(state.clone()),
+        ..Options::default()
+    };
+    process_payments_report(input.as_bytes(), options).unwrap();
+    let options = Options {
+        state_in: Some(state.clone()),
+        order: AccountOrder::Insertion,
+        ..Options::default()
+    };
+    let next = "type,client,tx,amount\ndeposit,0,5,1.0\ndeposit,1,6,1.0\n";
+    let report = process_payments_report(next.as_bytes(), options).unwrap();
+    fs::remove_file(&state).unwrap();
+    let clients: Vec<u16> = report.accounts.iter().map(|a| a.client).collect();
+    assert_eq!(clients, vec![3, 1, 2, 0]);
+}
+
+#[test]
+fn accounts_report_is_streamed_to_a_writer() {
+    let file = File::open("tests/resources/basic_example.csv").unwrap();
+    let report = process_payments_report(file, Options::default()).unwrap();
+    let mut out = Vec::new();
+    report.write_accounts_csv(&mut out).unwrap();
+    let expected = create_csv(vec![
+        ["1", "1.5000", "0.0000", "1.5000", "false"],
+        ["2", "2.0000", "0.0000", "2.0000", "false"],
+    ]);
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+}
+
+#[test]
+fn jsonl_input_gives_the_same_accounts_as_csv() {
+    let sut = process_payments(&OsString::from("tests/resources/basic_example.jsonl")).unwrap();
+    let expected = process_payments(&OsString::from("tests/resources/basic_example.csv")).unwrap();
+    assert_eq!(sut, expected);
+}
+
+#[test]
+fn jsonl_malformed_rows_are_handled_like_csv() {
+    let file = File::open("tests/resources/malformed_rows.jsonl").unwrap();
+    let options = Options {
+        input_format: InputFormat::Jsonl,
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options.clone()).unwrap_err();
+    assert!(matches!(sut, EngineError::Schema(_)));
+    assert_eq!(sut.to_string(), "input 1, line 2: missing amount");
+
+    let input = "{\"type\": \"deposit\", \"client\": 1,\n";
+    let sut = process_payments_report(input.as_bytes(), options.clone()).unwrap_err();
+    match &sut {
+        EngineError::Syntax { error, byte } => {
+            assert_eq!((error.line, error.column, *byte), (1, Some(32), 0))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(
+        sut.to_string(),
+        "input 1, line 1, column 32: EOF while parsing a value (byte 0)"
+    );
+
+    let file = File::open("tests/resources/malformed_rows.jsonl").unwrap();
+    let options = Options {
+        mode: ParseMode::Lenient,
+        ..options
+    };
+    let sut = process_payments_report(file, options).unwrap();
+    let expected = create_csv(vec![["1", "7.5000", "0.0000", "7.5000", "false"]]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+    let lines: Vec<u64> = sut.malformed.iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn duplicate_tx_ids_are_rejected_across_clients() {
+    let file = File::open("tests/resources/duplicate_tx_ids.csv").unwrap();
+    let sut = process_payments_report(file, keeping_rejections()).unwrap();
+    let expected = create_csv(vec![
+        ["1", "-10.0000", "100.0000", "90.0000", "false"],
+        ["2", "20.0000", "0.0000", "20.0000", "false"],
+    ]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+
+    let rejected: Vec<(u16, u32, TxError)> = sut
+        .rejections
+        .iter()
+        .map(|r| (r.client, r.tx, r.error))
+        .collect();
+    let expected_rejected = vec![
+        (1, 1, TxError::DuplicateTx),
+        (2, 1, TxError::DuplicateTx),
+        (2, 2, TxError::DuplicateTx),
+    ];
+    assert_eq!(rejected, expected_rejected);
+}
+
+#[test]
+fn cross_client_references_are_flagged() {
+    let file = File::open("tests/resources/cross_client_disputes.csv").unwrap();
+    let sut = process_payments_report(file, keeping_rejections()).unwrap();
+    let expected = create_csv(vec![
+        ["1", "100.0000", "0.0000", "100.0000", "false"],
+        ["2", "0.0000", "50.0000", "50.0000", "false"],
+    ]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+
+    let expected_flagged = "type,client,tx,reason\n\
+                            dispute,2,1,cross_client_tx\n\
+                            chargeback,2,1,cross_client_tx\n";
+    assert_eq!(sut.flagged_csv().unwrap(), expected_flagged);
+
+    let unknown: Vec<TxError> = sut
+        .rejections
+        .iter()
+        .filter(|r| !r.error.is_flagged())
+        .map(|r| r.error)
+        .collect();
+    assert_eq!(unknown, vec![TxError::UnknownTx]);
+}
+
+#[test]
+fn withdrawal_disputes_ignored_by_default() {
+    let file = File::open("tests/resources/withdrawal_reversal.csv").unwrap();
+    let sut = process_payments_report(file, Options::default()).unwrap();
+    let expected = create_csv(vec![
+        ["1", "70.0000", "0.0000", "70.0000", "false"],
+        ["2", "40.0000", "0.0000", "40.0000", "false"],
+    ]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+}
+
+#[test]
+fn withdrawal_reversal_policy_credits_back_disputed_withdrawals() {
+    let file = File::open("tests/resources/withdrawal_reversal.csv").unwrap();
+    let options = Options {
+        dispute_policy: Arc::new(WithdrawalReversal),
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap();
+    let expected = create_csv(vec![
+        ["1", "100.0000", "0.0000", "100.0000", "true"],
+        ["2", "40.0000", "0.0000", "40.0000", "false"],
+    ]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+    assert!(sut.rejections.is_empty());
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("toy_payments_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn state_is_resumed_from_snapshot() {
+    let state = temp_path("resume_state.json");
+
+    let day1 = File::open("tests/resources/resume_day1.csv").unwrap();
+    let options = Options {
+        state_out: Some(state.clone()),
+        ..Options::default()
+    };
+    process_payments_report(day1, options).unwrap();
+
+    let day2 = File::open("tests/resources/resume_day2.csv").unwrap();
+    let options = Options {
+        state_in: Some(state.clone()),
+        keep_rejections: true,
+        ..Options::default()
+    };
+    let sut = process_payments_report(day2, options).unwrap();
+    fs::remove_file(&state).unwrap();
+
+    // open dispute, queued withdrawal and seen tx ids all carry over
+    let expected = create_csv(vec![
+        ["1", "50.0000", "0.0000", "50.0000", "false"],
+        ["2", "0.0000", "10.0000", "10.0000", "false"],
+    ]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+    let errors: Vec<TxError> = sut.rejections.iter().map(|r| r.error).collect();
+    assert_eq!(errors, vec![TxError::DuplicateTx]);
+}
+
+#[test]
+fn multiple_inputs_share_account_state() {
+    let inputs = vec![
+        File::open("tests/resources/resume_day1.csv").unwrap(),
+        File::open("tests/resources/resume_day2.csv").unwrap(),
+    ];
+    let sut = process_payments_inputs(inputs, keeping_rejections()).unwrap();
+
+    // same result as resuming the second day from a snapshot of the first
+    let expected = create_csv(vec![
+        ["1", "50.0000", "0.0000", "50.0000", "false"],
+        ["2", "0.0000", "10.0000", "10.0000", "false"],
+    ]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+    let errors: Vec<TxError> = sut.rejections.iter().map(|r| r.error).collect();
+    assert_eq!(errors, vec![TxError::DuplicateTx]);
+}
+
+/// Accounts report with details after processing both resume inputs up to `stop_at`
+fn accounts_as_of(stop_at: StopAt) -> (String, bool) {
+    let inputs = vec![
+        File::open("tests/resources/resume_day1.csv").unwrap(),
+        File::open("tests/resources/resume_day2.csv").unwrap(),
+    ];
+    let options = Options {
+        stop_at: Some(stop_at),
+        ..Options::default()
+    };
+    let sut = process_payments_inputs(inputs, options).unwrap();
+    let mut out = Vec::new();
+    sut.write_accounts(&mut out, ReportFormat::Csv, true)
+        .unwrap();
+    (String::from_utf8(out).unwrap(), sut.stopped_early)
+}
+
+#[test]
+fn processing_stops_at_a_row_or_tx() {
+    // rows are counted across inputs, so row 5 is the second input's resolve
+    let (accounts, stopped) = accounts_as_of(StopAt::Row(5));
+    assert!(stopped);
+    assert_eq!(
+        accounts,
+        "client,available,held,total,locked,open_disputes,pending_withdrawals\n\
+         1,0.0000,100.0000,100.0000,false,1,50.0000\n\
+         2,10.0000,0.0000,10.0000,false,0,0.0000\n"
+    );
+
+    let (accounts, stopped) = accounts_as_of(StopAt::Tx(3));
+    assert!(stopped);
+    assert_eq!(
+        accounts,
+        "client,available,held,total,locked,open_disputes,pending_withdrawals\n\
+         1,0.0000,100.0000,100.0000,false,1,50.0000\n"
+    );
+
+    let (_, stopped) = accounts_as_of(StopAt::Tx(99));
+    assert!(!stopped);
+}
+
+#[test]
+fn stopping_early_cannot_record_the_run() {
+    let path = temp_path("stopped_run");
+    let recording = [
+        Options {
+            state_out: Some(path.clone()),
+            ..Options::default()
+        },
+        Options {
+            journal: Some(path.clone()),
+            ..Options::default()
+        },
+        Options {
+            audit: Some(path.clone()),
+            ..Options::default()
+        },
+    ];
+    for options in recording {
+        let file = File::open("tests/resources/resume_day1.csv").unwrap();
+        let options = Options {
+            stop_at: Some(StopAt::Row(2)),
+            ..options
+        };
+        let sut = process_payments_report(file, options).unwrap_err();
+        assert!(matches!(sut, EngineError::Options(_)));
+        assert!(!path.exists());
+    }
+}
+
+/// Runs the basic example after compressing it to a temporary file named `name`
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn process_compressed_example(name: &str, compression: Compression) -> String {
+    let path = temp_path(name);
+    let input = fs::read("tests/resources/basic_example.csv").unwrap();
+    write_compressed(File::create(&path).unwrap(), &input, compression).unwrap();
+    let sut = process_payments(&path.clone().into_os_string()).unwrap();
+    fs::remove_file(&path).unwrap();
+    sut
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip_input_is_decompressed() {
+    let sut = process_compressed_example("basic_example.csv.gz", Compression::Gzip);
+    let expected = process_payments(&OsString::from("tests/resources/basic_example.csv")).unwrap();
+    assert_eq!(sut, expected);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_input_is_detected_without_extension() {
+    let sut = process_compressed_example("basic_example_zstd.csv", Compression::Zstd);
+    let expected = process_payments(&OsString::from("tests/resources/basic_example.csv")).unwrap();
+    assert_eq!(sut, expected);
+}
+
+#[test]
+fn snapshot_with_unknown_version_is_rejected() {
+    let state = temp_path("future_state.json");
+    fs::write(&state, r#"{"version":999,"clients":[],"tx_owners":{}}"#).unwrap();
+
+    let file = File::open("tests/resources/basic_example.csv").unwrap();
+    let options = Options {
+        state_in: Some(state.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap_err();
+    fs::remove_file(&state).unwrap();
+    assert_eq!(
+        sut.to_string(),
+        format!(
+            "snapshot version 999 is not supported, expected {}",
+            SNAPSHOT_VERSION
+        )
+    );
+}
+
+#[test]
+fn statement_lists_every_transaction_with_resulting_balances() {
+    let state = temp_path("history_state.json");
+    let options = Options {
+        state_out: Some(state.clone()),
+        history: true,
+        ..Options::default()
+    };
+    let day1 = "type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\nwithdrawal,1,2,3\n";
+    process_payments_report(day1.as_bytes(), options).unwrap();
+
+    // history carries over the snapshot
+    let options = Options {
+        state_in: Some(state.clone()),
+        history: true,
+        ..Options::default()
+    };
+    let mut engine = Engine::from_options(&options).unwrap();
+    fs::remove_file(&state).unwrap();
+    let day2 = "type,client,tx,amount\nresolve,1,1,\nwithdrawal,1,3,100\n";
+    engine
+        .process_csv(day2.as_bytes(), ParseMode::Strict)
+        .unwrap();
+
+    let mut statement = Vec::new();
+    engine
+        .write_statement(1, &mut statement, ReportFormat::Csv)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(statement).unwrap(),
+        "type,tx,amount,status,reason,available,held,total,locked\n\
+         deposit,1,10.0000,ok,,10.0000,0.0000,10.0000,false\n\
+         dispute,1,,ok,,0.0000,10.0000,10.0000,false\n\
+         withdrawal,2,3.0000,queued,,0.0000,10.0000,10.0000,false\n\
+         resolve,1,,ok,,7.0000,0.0000,7.0000,false\n\
+         withdrawal,3,100.0000,rejected,insufficient_funds,7.0000,0.0000,7.0000,false\n"
+    );
+    assert!(engine.history(2).is_none());
+}
+
+#[test]
+fn version_1_snapshots_load_without_history() {
+    let state = temp_path("v1_state.json");
+    let client = r#"{"client":1,"available":"1","held":"0","total":"1","locked":false,"txs":{},"disputed_txs":[],"rejected_txs":[]}"#;
+    let snapshot = format!(r#"{{"version":1,"clients":[{}],"tx_owners":{{}}}}"#, client);
+    fs::write(&state, snapshot).unwrap();
+
+    let options = Options {
+        state_in: Some(state.clone()),
+        history: true,
+        ..Options::default()
+    };
+    let engine = Engine::from_options(&options).unwrap();
+    fs::remove_file(&state).unwrap();
+    assert_eq!(engine.account(1).unwrap().total, "1".parse().unwrap());
+    assert_eq!(engine.history(1), Some(&[][..]));
+}
+
+/// Simulates a run which crashed after journaling the first `rows` rows of `csv_path`
+fn interrupted_run(csv_path: &str, rows: usize, journal: &Path) {
+    let content = fs::read_to_string(csv_path).unwrap();
+    let partial: Vec<&str> = content.lines().take(rows + 1).collect();
+    let options = Options {
+        journal: Some(journal.to_path_buf()),
+        ..Options::default()
+    };
+    process_payments_report(partial.join("\n").as_bytes(), options).unwrap();
+}
+
+#[test]
+fn journaled_rows_are_replayed_and_skipped_after_crash() {
+    let journal = temp_path("crash_journal.log");
+    interrupted_run("tests/resources/retroactive_resolve.csv", 5, &journal);
+
+    let file = File::open("tests/resources/retroactive_resolve.csv").unwrap();
+    let options = Options {
+        journal: Some(journal.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap();
+    let journaled = fs::read_to_string(&journal).unwrap().lines().count();
+    fs::remove_file(&journal).unwrap();
+
+    let expected = create_csv(vec![["1", "0.0000", "0.0000", "0.0000", "false"]]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+    assert_eq!(journaled, 9);
+}
+
+#[test]
+fn torn_journal_record_is_discarded() {
+    let journal = temp_path("torn_journal.log");
+    interrupted_run("tests/resources/retroactive_resolve.csv", 3, &journal);
+    let mut content = fs::read_to_string(&journal).unwrap();
+    content.push_str("1234abcd {\"seq\":3,\"row\":{\"ty");
+    fs::write(&journal, content).unwrap();
+
+    let file = File::open("tests/resources/retroactive_resolve.csv").unwrap();
+    let options = Options {
+        journal: Some(journal.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap();
+    let journaled = fs::read_to_string(&journal).unwrap().lines().count();
+    fs::remove_file(&journal).unwrap();
+
+    let expected = create_csv(vec![["1", "0.0000", "0.0000", "0.0000", "false"]]);
+    assert_eq!(sut.accounts_csv().unwrap(), expected);
+    assert_eq!(journaled, 9);
+}
+
+#[test]
+fn corrupt_journal_record_aborts_recovery() {
+    let journal = temp_path("corrupt_journal.log");
+    interrupted_run("tests/resources/retroactive_resolve.csv", 3, &journal);
+    let content = fs::read_to_string(&journal)
+        .unwrap()
+        .replacen("100", "900", 1);
+    fs::write(&journal, content).unwrap();
+
+    let file = File::open("tests/resources/retroactive_resolve.csv").unwrap();
+    let options = Options {
+        journal: Some(journal.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap_err();
+    fs::remove_file(&journal).unwrap();
+    assert_eq!(sut.to_string(), "journal record 1 is corrupt");
+}
+
+#[test]
+fn journal_from_another_input_is_not_skipped() {
+    let journal = temp_path("stale_journal.log");
+    interrupted_run("tests/resources/resume_day1.csv", 2, &journal);
+
+    let file = File::open("tests/resources/resume_day2.csv").unwrap();
+    let options = Options {
+        journal: Some(journal.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(file, options).unwrap_err();
+    fs::remove_file(&journal).unwrap();
+    assert_eq!(
+        sut.to_string(),
+        "input 1 line 2 does not match the journal, which may be left over from another run"
+    );
+}
+
+#[test]
+fn journal_is_emptied_once_state_is_saved() {
+    let journal = temp_path("checkpoint_journal.log");
+    let state = temp_path("checkpoint_state.json");
+    let file = File::open("tests/resources/resume_day1.csv").unwrap();
+    let options = Options {
+        journal: Some(journal.clone()),
+        state_out: Some(state.clone()),
+        ..Options::default()
+    };
+    process_payments_report(file, options).unwrap();
+    let journaled = fs::read_to_string(&journal).unwrap();
+    fs::remove_file(&journal).unwrap();
+    fs::remove_file(&state).unwrap();
+    assert_eq!(journaled, "");
+}
+
+/// Deterministic mix of every transaction type, including rejected rows, across many clients
+fn mixed_transactions(rows: u32) -> String {
+    let mut seed: u64 = 42;
+    let mut next = |bound: u32| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((seed >> 33) % bound as u64) as u32
+    };
+
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 1..=rows {
+        let client = next(50);
+        let earlier = next(tx) + 1;
+        let line = match next(10) {
+            0..=3 => format!("deposit,{},{},{}.{}", client, tx, next(1000) + 1, next(10)),
+            4..=6 => format!("withdrawal,{},{},{}", client, tx, next(300) + 1),
+            7 => format!("dispute,{},{},", client, earlier),
+            8 => format!("resolve,{},{},", client, earlier),
+            _ => format!("chargeback,{},{},", client, earlier),
+        };
+        csv.push_str(&line);
+        csv.push('\n');
+    }
+    csv
+}
+
+#[test]
+fn sharded_processing_matches_single_thread() {
+    let input = mixed_transactions(20_000);
+
+    let options = Options {
+        order: AccountOrder::Insertion,
+        keep_rejections: true,
+        ..Options::default()
+    };
+    let single = process_payments_report(input.as_bytes(), options.clone()).unwrap();
+    let options = Options {
+        threads: 4,
+        ..options
+    };
+    let sharded = process_payments_report(input.as_bytes(), options).unwrap();
+
+    assert_eq!(sharded.accounts, single.accounts);
+    assert_eq!(sharded.rejections, single.rejections);
+    assert!(!single.rejections.is_empty());
+}
+
+/// Accounts csv rebuilt from the audit log at `path` written under `policy`, which is then
+/// removed
+fn replayed_accounts_csv(path: &Path, policy: &dyn DisputePolicy) -> String {
+    let replayed = PaymentsReport {
+        accounts: replay_audit(File::open(path).unwrap(), policy).unwrap(),
+        rejections: Vec::new(),
+        flagged_count: 0,
+        malformed: Vec::new(),
+        stopped_early: false,
+    };
+    fs::remove_file(path).unwrap();
+    replayed.accounts_csv().unwrap()
+}
+
+#[test]
+fn audit_log_replays_to_the_same_accounts() {
+    let input = mixed_transactions(5_000);
+    let single_log = temp_path("audit_single.jsonl");
+    let sharded_log = temp_path("audit_sharded.jsonl");
+
+    let options = Options {
+        audit: Some(single_log.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(input.as_bytes(), options).unwrap();
+    let options = Options {
+        audit: Some(sharded_log.clone()),
+        threads: 4,
+        ..Options::default()
+    };
+    process_payments_report(input.as_bytes(), options).unwrap();
+
+    // workers' events are merged back in input order
+    let single_events = fs::read_to_string(&single_log).unwrap();
+    assert_eq!(fs::read_to_string(&sharded_log).unwrap(), single_events);
+    let first = single_events.lines().next().unwrap();
+    assert!(first.starts_with("{\"op\":\"open\""));
+    assert!(first.contains("\"input\":1,\"line\":"));
+
+    let expected = sut.accounts_csv().unwrap();
+    assert_eq!(replayed_accounts_csv(&single_log, &DepositsOnly), expected);
+    assert_eq!(replayed_accounts_csv(&sharded_log, &DepositsOnly), expected);
+}
+
+#[test]
+fn audit_log_replays_under_the_policy_it_was_written_with() {
+    let input = mixed_transactions(2_000);
+    let log = temp_path("audit_reversal.jsonl");
+    let options = Options {
+        dispute_policy: Arc::new(WithdrawalReversal),
+        audit: Some(log.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(input.as_bytes(), options).unwrap();
+
+    let events = fs::read_to_string(&log).unwrap();
+    assert!(events.contains("\"disputed\":\"withdrawal\""));
+    assert!(replay_audit(events.as_bytes(), &DepositsOnly).is_err());
+    let expected = sut.accounts_csv().unwrap();
+    assert_eq!(replayed_accounts_csv(&log, &WithdrawalReversal), expected);
+}
+
+#[test]
+fn audit_log_starts_from_restored_accounts() {
+    let state = temp_path("audit_state.json");
+    let log = temp_path("audit_resumed.jsonl");
+
+    let day1 = File::open("tests/resources/resume_day1.csv").unwrap();
+    let options = Options {
+        state_out: Some(state.clone()),
+        ..Options::default()
+    };
+    process_payments_report(day1, options).unwrap();
+
+    let day2 = File::open("tests/resources/resume_day2.csv").unwrap();
+    let options = Options {
+        state_in: Some(state.clone()),
+        audit: Some(log.clone()),
+        ..Options::default()
+    };
+    let sut = process_payments_report(day2, options).unwrap();
+    fs::remove_file(&state).unwrap();
+
+    let events = fs::read_to_string(&log).unwrap();
+    assert!(events.starts_with("{\"op\":\"restore\",\"client\":1,\"tx\":null"));
+    assert!(events.contains("\"op\":\"resolve\""));
+    assert!(events.contains("\"released\":\"50.0000\""));
+    assert_eq!(
+        replayed_accounts_csv(&log, &DepositsOnly),
+        sut.accounts_csv().unwrap()
+    );
+}
+
+/// Starts a server on a free localhost port, returning a connection to it
+fn connect_to_server() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || serve_payments(listener, &Options::default()).unwrap());
+    TcpStream::connect(addr).unwrap()
+}
+
+fn send_lines(stream: &TcpStream, lines: &[&str]) -> Vec<String> {
+    let mut writer = stream;
+    let mut reader = BufReader::new(stream);
+    lines
+        .iter()
+        .map(|line| {
+            writeln!(writer, "{}", line).unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).unwrap();
+            response.trim_end().to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn server_applies_rows_and_answers_queries() {
+    let stream = connect_to_server();
+    let responses = send_lines(
+        &stream,
+        &[
+            "deposit, 1, 1, 10.0",
+            "withdrawal, 1, 2, 2.5",
+            "withdrawal, 1, 3, 100.0",
+            "dispute, 1, 1,",
+            "query 1",
+            "deposit, 1, 1, 5.0",
+            "deposit, 1, x, 5.0",
+            "query 2",
+        ],
+    );
+    assert_eq!(
+        responses,
+        vec![
+            "ok",
+            "ok",
+            "rejected insufficient_funds",
+            "ok",
+            "1,-2.5000,10.0000,7.5000,false",
+            "rejected duplicate_tx",
+            "error line 7, column 3: invalid transaction id 'x'",
+            "error unknown client '2'",
+        ]
+    );
+
+    // state is shared between connections
+    let other = TcpStream::connect(stream.peer_addr().unwrap()).unwrap();
+    assert_eq!(
+        send_lines(&other, &["query 1"]),
+        vec!["1,-2.5000,10.0000,7.5000,false"]
+    );
+}